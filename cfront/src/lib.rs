@@ -0,0 +1,913 @@
+use lexer::{Lexeme, Token};
+
+use std::collections::HashMap;
+
+// translates a subset of c into lexemes so programs can be written as text and rendered with
+// lexer::serialize. the subset is what cram can express:
+// - `int` and `long` variables with an optional initialiser
+// - assignments of integer literals, character literals, variables and calls
+// - adding and subtracting them with `+` and `-`, `++`, `--`, `+=` and `-=`, as long as a literal
+//   isnt on the left of an operator since the increments would continue the literal
+// - `while` loops on any value and `if` statements on a literal or a variable
+// - printing text with `printf("text")` and characters with `putchar('a')`, without format specifiers
+// - reading a byte with `getchar()`, which is zero instead of EOF once stdin is empty
+// - functions with `int` and `long` parameters, returning at the end of their body
+// - calls with literal and variable arguments to functions defined before them, but not recursion
+// - the body of `main` becomes the top level
+// names are global so parameters cant share names with anything else
+
+#[derive(Debug, Clone, PartialEq)]
+enum CToken {
+    Identifier(String),
+    Int(isize), // integer and character literals
+    String(String),
+    Punctuation(char),
+}
+
+// splits the source into tokens paired with their line
+fn tokenize(source: &str) -> Result<Vec<(CToken, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => (),
+            // preprocessor directives are skipped
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            last = c;
+                        }
+                        None => return Err(format!("unterminated comment at line {line}")),
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+                    word.push(c);
+                }
+                tokens.push((CToken::Identifier(word), line));
+            }
+            c if c.is_ascii_digit() => {
+                let mut digits = c.to_string();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                let int = digits
+                    .parse()
+                    .map_err(|_| format!("integer too large at line {line}"))?;
+                tokens.push((CToken::Int(int), line));
+            }
+            '\'' => {
+                let c = match chars.next() {
+                    Some('\\') => escape(chars.next(), line)?,
+                    Some(c) => c,
+                    None => return Err(format!("unterminated character at line {line}")),
+                };
+                if chars.next() != Some('\'') {
+                    return Err(format!("unterminated character at line {line}"));
+                }
+                tokens.push((CToken::Int(c as isize), line));
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => string.push(escape(chars.next(), line)?),
+                        Some('\n') | None => {
+                            return Err(format!("unterminated string at line {line}"))
+                        }
+                        Some(c) => string.push(c),
+                    }
+                }
+                tokens.push((CToken::String(string), line));
+            }
+            '{' | '}' | '(' | ')' | ';' | ',' | '=' | '+' | '-' => {
+                tokens.push((CToken::Punctuation(c), line))
+            }
+            c => return Err(format!("unsupported character `{c}` at line {line}")),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn escape(c: Option<char>, line: usize) -> Result<char, String> {
+    match c {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('0') => Ok('\0'),
+        Some(c @ ('\\' | '\'' | '"')) => Ok(c),
+        _ => Err(format!("unsupported escape at line {line}")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NameKind {
+    Variable,
+    Parameter,       // a variable declared in a functions signature
+    Function(usize), // amount of parameters
+}
+
+struct Translator {
+    tokens: Vec<(CToken, usize)>,
+    position: usize,
+
+    names: HashMap<String, usize>, // name to its id in `kinds`
+    kinds: Vec<NameKind>,
+    current: Option<usize>, // function being translated, None in main
+
+    functions: Vec<Lexeme>,    // function scopes
+    initialisers: Vec<Lexeme>, // initialisers of global variables
+    main: Vec<Lexeme>,
+}
+
+impl Translator {
+    fn new(tokens: Vec<(CToken, usize)>) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            names: HashMap::new(),
+            kinds: Vec::new(),
+            current: None,
+            functions: Vec::new(),
+            initialisers: Vec::new(),
+            main: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<&CToken> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    // line of the current token, or of the last token at the end of the source
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn next(&mut self) -> Result<CToken, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or(format!("unexpected end of file at line {}", self.line()))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next()? {
+            CToken::Punctuation(p) if p == c => Ok(()),
+            _ => Err(format!(
+                "expected `{c}` at line {}",
+                self.tokens[self.position - 1].1
+            )),
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(&CToken::Punctuation(c));
+        self.position += found as usize;
+        found
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.next()? {
+            CToken::Identifier(name) => Ok(name),
+            _ => Err(format!(
+                "expected a name at line {}",
+                self.tokens[self.position - 1].1
+            )),
+        }
+    }
+
+    fn is_type(name: &str) -> bool {
+        matches!(name, "int" | "long" | "void")
+    }
+
+    fn declare(&mut self, name: String, kind: NameKind) -> Result<usize, String> {
+        if self.names.contains_key(&name) {
+            return Err(format!(
+                "`{name}` is already declared at line {}",
+                self.line()
+            ));
+        }
+
+        self.kinds.push(kind);
+        self.names.insert(name, self.kinds.len() - 1);
+        Ok(self.kinds.len() - 1)
+    }
+
+    fn variable(&self, name: &str) -> Result<usize, String> {
+        match self.names.get(name) {
+            Some(&id) if matches!(self.kinds[id], NameKind::Variable | NameKind::Parameter) => {
+                Ok(id)
+            }
+            Some(_) => Err(format!(
+                "functions cant be used as values at line {}",
+                self.line()
+            )),
+            None => Err(format!("`{name}` is undeclared at line {}", self.line())),
+        }
+    }
+
+    fn translate(&mut self) -> Result<(), String> {
+        while self.peek().is_some() {
+            let kind = self.identifier()?;
+            if !Self::is_type(&kind) {
+                return Err(format!("expected a type at line {}", self.line()));
+            }
+            let name = self.identifier()?;
+
+            if self.eat('(') {
+                let parameters = self.parameters()?;
+                // prototypes dont matter since functions are scopes
+                if self.eat(';') {
+                    continue;
+                }
+                if name == "main" {
+                    self.current = None;
+                    let mut body = self.block(true)?;
+                    self.main.append(&mut body);
+                    continue;
+                }
+
+                // the name is the return value until the function is defined
+                let id = self.declare(name, NameKind::Variable)?;
+                let mut signature = vec![
+                    Lexeme::Token(Token::ScopeStart),
+                    Lexeme::Token(Token::Access),
+                    Lexeme::Identifier(id),
+                ];
+                for parameter in &parameters {
+                    let parameter = self.declare(parameter.clone(), NameKind::Parameter)?;
+                    signature.push(Lexeme::Token(Token::Access));
+                    signature.push(Lexeme::Identifier(parameter));
+                }
+                signature.push(Lexeme::Token(Token::LineBreak));
+
+                self.current = Some(id);
+                let mut body = self.block(true)?;
+                self.kinds[id] = NameKind::Function(parameters.len());
+                self.functions.append(&mut signature);
+                self.functions.append(&mut body);
+                self.functions.push(Lexeme::Token(Token::ScopeEnd));
+            } else {
+                let mut initialisers = self.declaration(name)?;
+                self.initialisers.append(&mut initialisers);
+            }
+        }
+
+        Ok(())
+    }
+
+    // names of the parameters after the opening parenthesis
+    fn parameters(&mut self) -> Result<Vec<String>, String> {
+        let mut parameters = Vec::new();
+        if self.peek() == Some(&CToken::Identifier("void".to_string())) {
+            self.position += 1;
+        }
+
+        while !self.eat(')') {
+            if !parameters.is_empty() {
+                self.expect(',')?;
+            }
+            match self.identifier()?.as_str() {
+                "int" | "long" => parameters.push(self.identifier()?),
+                _ => {
+                    return Err(format!(
+                        "parameters have to be int or long at line {}",
+                        self.line()
+                    ))
+                }
+            }
+        }
+
+        Ok(parameters)
+    }
+
+    // a call after the functions name, its arguments follow it
+    fn call(&mut self, name: &str) -> Result<Vec<Lexeme>, String> {
+        let line = self.line();
+        let (id, parameters) = match self.names.get(name) {
+            Some(&id) if Some(id) == self.current => {
+                return Err(format!("recursion isnt supported at line {line}"))
+            }
+            Some(&id) => match self.kinds[id] {
+                NameKind::Function(parameters) => (id, parameters),
+                _ => return Err(format!("`{name}` isnt a function at line {line}")),
+            },
+            None => return Err(format!("`{name}` is undeclared at line {line}")),
+        };
+
+        self.expect('(')?;
+        let mut lexemes = vec![Lexeme::Identifier(id)];
+        let mut arguments = 0;
+        while !self.eat(')') {
+            if arguments != 0 {
+                self.expect(',')?;
+            }
+            lexemes.append(&mut self.argument()?);
+            arguments += 1;
+        }
+        if arguments != parameters {
+            return Err(format!(
+                "`{name}` takes {parameters} arguments but got {arguments} at line {line}"
+            ));
+        }
+
+        Ok(lexemes)
+    }
+
+    // the rest of a declaration after the first name
+    fn declaration(&mut self, name: String) -> Result<Vec<Lexeme>, String> {
+        let mut lexemes = Vec::new();
+        let mut name = name;
+
+        loop {
+            let id = self.declare(name, NameKind::Variable)?;
+            // variables start at zero so only initialisers need a statement
+            if self.eat('=') {
+                lexemes.append(&mut self.expression()?);
+                lexemes.push(Lexeme::Identifier(id));
+                lexemes.push(Lexeme::Token(Token::LineBreak));
+            }
+
+            if !self.eat(',') {
+                break;
+            }
+            name = self.identifier()?;
+        }
+        self.expect(';')?;
+
+        Ok(lexemes)
+    }
+
+    // the arguments of getchar
+    fn input(&mut self) -> Result<Vec<Lexeme>, String> {
+        self.expect('(')?;
+        self.expect(')')?;
+
+        Ok(vec![Lexeme::Token(Token::Input)])
+    }
+
+    // a literal, a variable, a call or getchar
+    fn value(&mut self) -> Result<Vec<Lexeme>, String> {
+        match (self.peek(), self.tokens.get(self.position + 1)) {
+            (Some(CToken::Identifier(name)), Some((CToken::Punctuation('('), _)))
+                if name == "getchar" =>
+            {
+                self.position += 1;
+                self.input()
+            }
+            (Some(CToken::Identifier(name)), Some((CToken::Punctuation('('), _))) => {
+                let name = name.clone();
+                self.position += 1;
+                self.call(&name)
+            }
+            _ => self.argument(),
+        }
+    }
+
+    // values added and subtracted from left to right
+    fn expression(&mut self) -> Result<Vec<Lexeme>, String> {
+        let mut lexemes = self.value()?;
+
+        while let Some(&CToken::Punctuation(c @ ('+' | '-'))) = self.peek() {
+            if let Some(Lexeme::Token(Token::Zero | Token::Increment | Token::Decrement)) =
+                lexemes.last()
+            {
+                return Err(format!(
+                    "literals cant be on the left of an operator at line {}",
+                    self.line()
+                ));
+            }
+            self.position += 1;
+            lexemes.push(step(c));
+            lexemes.append(&mut self.argument()?);
+        }
+
+        Ok(lexemes)
+    }
+
+    // a literal or a variable
+    fn argument(&mut self) -> Result<Vec<Lexeme>, String> {
+        let negative = self.eat('-');
+        match self.next()? {
+            CToken::Int(int) => Ok(int_lit(if negative { -int } else { int })),
+            CToken::Identifier(name) if !negative => {
+                Ok(vec![Lexeme::Identifier(self.variable(&name)?)])
+            }
+            _ => Err(format!(
+                "only literals and variables are supported as values at line {}",
+                self.tokens[self.position - 1].1
+            )),
+        }
+    }
+
+    // the condition of an if, which the parser only tells apart from other scopes when it starts
+    // with a literal or a variable
+    fn condition(&mut self) -> Result<Vec<Lexeme>, String> {
+        let line = self.line();
+        let call = self.tokens.get(self.position + 1).map(|(token, _)| token)
+            == Some(&CToken::Punctuation('('));
+        let condition = match call {
+            true => None,
+            false => Some(self.argument()?),
+        };
+        match condition {
+            Some(condition) if self.peek() == Some(&CToken::Punctuation(')')) => Ok(condition),
+            _ => Err(format!(
+                "if conditions can only be a literal or a variable at line {line}"
+            )),
+        }
+    }
+
+    // statements between braces, a return is allowed at the end of a function
+    fn block(&mut self, function: bool) -> Result<Vec<Lexeme>, String> {
+        self.expect('{')?;
+
+        let mut lexemes = Vec::new();
+        while !self.eat('}') {
+            if function && self.peek() == Some(&CToken::Identifier("return".to_string())) {
+                self.position += 1;
+                if !self.eat(';') {
+                    let mut value = self.expression()?;
+                    self.expect(';')?;
+                    // functions return whatever is stored in their name
+                    // mains value is thrown away since cram has no exit codes
+                    if let Some(id) = self.current {
+                        lexemes.append(&mut value);
+                        lexemes.push(Lexeme::Identifier(id));
+                        lexemes.push(Lexeme::Token(Token::LineBreak));
+                    }
+                }
+                if self.peek() != Some(&CToken::Punctuation('}')) {
+                    return Err(format!(
+                        "return is only supported at the end of a function at line {}",
+                        self.line()
+                    ));
+                }
+                continue;
+            }
+
+            lexemes.append(&mut self.statement()?);
+        }
+
+        Ok(lexemes)
+    }
+
+    fn statement(&mut self) -> Result<Vec<Lexeme>, String> {
+        let line = self.line();
+        let name = self.identifier()?;
+
+        match name.as_str() {
+            kind if Self::is_type(kind) => {
+                let name = self.identifier()?;
+                self.declaration(name)
+            }
+            // an if is a scope whose signature is only its condition
+            "while" | "if" => {
+                self.expect('(')?;
+                let mut lexemes = vec![Lexeme::Token(Token::ScopeStart)];
+                if name == "while" {
+                    lexemes.push(Lexeme::Token(Token::Repeat));
+                }
+                lexemes.append(&mut match name.as_str() {
+                    "while" => self.expression()?,
+                    _ => self.condition()?,
+                });
+                lexemes.push(Lexeme::Token(Token::LineBreak));
+                self.expect(')')?;
+
+                match self.peek() {
+                    Some(CToken::Punctuation('{')) => lexemes.append(&mut self.block(false)?),
+                    _ => lexemes.append(&mut self.statement()?),
+                }
+                lexemes.push(Lexeme::Token(Token::ScopeEnd));
+
+                Ok(lexemes)
+            }
+            "putchar" => {
+                self.expect('(')?;
+                let string = match self.next()? {
+                    CToken::Int(c) => u32::try_from(c)
+                        .ok()
+                        .filter(|&c| c != 0)
+                        .and_then(char::from_u32)
+                        .map(String::from),
+                    _ => None,
+                }
+                .ok_or(format!("only characters can be printed at line {line}"))?;
+                self.expect(')')?;
+                self.expect(';')?;
+
+                Ok(quote(&string))
+            }
+            "getchar" => {
+                let mut lexemes = self.input()?;
+                self.expect(';')?;
+                lexemes.push(Lexeme::Token(Token::LineBreak));
+
+                Ok(lexemes)
+            }
+            "printf" => {
+                self.expect('(')?;
+                let string = match self.next()? {
+                    // the string has to be printed back exactly
+                    CToken::String(string) if !string.contains(['%', '\0']) => Some(string),
+                    _ => None,
+                }
+                .ok_or(format!("only plain strings can be printed at line {line}"))?;
+                self.expect(')')?;
+                self.expect(';')?;
+
+                Ok(quote(&string))
+            }
+            _ if self.peek() == Some(&CToken::Punctuation('(')) => {
+                let mut lexemes = self.call(&name)?;
+                self.expect(';')?;
+                lexemes.push(Lexeme::Token(Token::LineBreak));
+
+                Ok(lexemes)
+            }
+            _ => {
+                let id = self.variable(&name)?;
+                let mut lexemes = match self.next()? {
+                    CToken::Punctuation('=') => {
+                        let mut lexemes = self.expression()?;
+                        lexemes.push(Lexeme::Identifier(id));
+                        lexemes
+                    }
+                    // an increment or decrement after a variable changes it in place
+                    CToken::Punctuation(c @ ('+' | '-')) if self.eat(c) => {
+                        vec![Lexeme::Identifier(id), step(c)]
+                    }
+                    CToken::Punctuation(c @ ('+' | '-')) => {
+                        self.expect('=')?;
+                        let mut lexemes = vec![Lexeme::Identifier(id), step(c)];
+                        lexemes.append(&mut self.argument()?);
+                        lexemes.push(Lexeme::Identifier(id));
+                        lexemes
+                    }
+                    _ => return Err(format!("expected an assignment at line {line}")),
+                };
+                self.expect(';')?;
+                lexemes.push(Lexeme::Token(Token::LineBreak));
+
+                Ok(lexemes)
+            }
+        }
+    }
+
+    // every variable is declared at the top since the lexer has to see a declaration before its use
+    // the lexer numbers identifiers in the order they are declared so the ids are remapped to that
+    fn finish(self) -> Vec<Lexeme> {
+        let mut lexemes = Vec::new();
+        for (id, kind) in self.kinds.iter().enumerate() {
+            if *kind == NameKind::Variable {
+                lexemes.extend([
+                    Lexeme::Token(Token::Access),
+                    Lexeme::Identifier(id),
+                    Lexeme::Token(Token::LineBreak),
+                ]);
+            }
+        }
+        lexemes.extend(self.functions);
+        lexemes.extend(self.initialisers);
+        lexemes.extend(self.main);
+
+        // an access declares the identifier after it
+        let mut ids = vec![0; self.kinds.len()];
+        let declared = lexemes.windows(2).filter_map(|pair| match pair {
+            [Lexeme::Token(Token::Access), Lexeme::Identifier(id)] => Some(*id),
+            _ => None,
+        });
+        for (new, old) in declared.enumerate() {
+            ids[old] = new;
+        }
+
+        lexemes
+            .into_iter()
+            .map(|lexeme| match lexeme {
+                Lexeme::Identifier(id) => Lexeme::Identifier(ids[id]),
+                lexeme => lexeme,
+            })
+            .collect()
+    }
+}
+
+// zero incremented or decremented to the value
+fn int_lit(int: isize) -> Vec<Lexeme> {
+    let step = match int < 0 {
+        true => Token::Decrement,
+        false => Token::Increment,
+    };
+
+    let mut lexemes = vec![Lexeme::Token(Token::Zero)];
+    lexemes.extend((0..int.unsigned_abs()).map(|_| Lexeme::Token(step)));
+    lexemes
+}
+
+// the operator written as `c`
+fn step(c: char) -> Lexeme {
+    match c {
+        '-' => Lexeme::Token(Token::Decrement),
+        _ => Lexeme::Token(Token::Increment),
+    }
+}
+
+// a quote printing the string, the first character starts at the quote and the rest at a zero
+fn quote(string: &str) -> Vec<Lexeme> {
+    let mut lexemes = vec![Lexeme::Token(Token::Quote)];
+    for (i, c) in string.chars().enumerate() {
+        let mut int = int_lit(c as isize);
+        if i == 0 {
+            int.remove(0);
+        }
+        lexemes.append(&mut int);
+    }
+    lexemes.push(Lexeme::Token(Token::Quote));
+    lexemes.push(Lexeme::Token(Token::LineBreak));
+    lexemes
+}
+
+// translates the c source into lexemes
+pub fn translate(source: &str) -> Result<Vec<Lexeme>, String> {
+    let mut translator = Translator::new(tokenize(source)?);
+    translator.translate()?;
+
+    Ok(translator.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Lexeme::Identifier as Id;
+    use Token::*;
+
+    fn t(token: Token) -> Lexeme {
+        Lexeme::Token(token)
+    }
+
+    fn increments(n: usize) -> Vec<Lexeme> {
+        vec![t(Increment); n]
+    }
+
+    #[test]
+    fn cfront_tokenize() {
+        let test = tokenize("#include <stdio.h>\nlong a = 'b'; // c\n/* d\n */ \"e\\n\"").unwrap();
+        let expected = vec![
+            (CToken::Identifier("long".to_string()), 2),
+            (CToken::Identifier("a".to_string()), 2),
+            (CToken::Punctuation('='), 2),
+            (CToken::Int(98), 2),
+            (CToken::Punctuation(';'), 2),
+            (CToken::String("e\n".to_string()), 4),
+        ];
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn cfront_translate() {
+        let test = translate(
+            "
+void f(void);
+
+void f(void) {
+    printf(\"\\t\\n\");
+}
+
+long a = 2;
+
+int main(void) {
+    long b;
+    while (a) {
+        putchar('\\n');
+        a = -1;
+        b = a;
+    }
+    if (b) putchar('\\t');
+    return 0;
+}
+",
+        )
+        .unwrap();
+        let expected = [
+            // declarations
+            vec![
+                t(Access),
+                Id(0),
+                t(LineBreak),
+                t(Access),
+                Id(1),
+                t(LineBreak),
+            ],
+            // f
+            vec![t(ScopeStart), t(Access), Id(2), t(LineBreak), t(Quote)],
+            increments(9),
+            vec![t(Zero)],
+            increments(10),
+            vec![t(Quote), t(LineBreak), t(ScopeEnd)],
+            // a = 2
+            vec![t(Zero), t(Increment), t(Increment), Id(0), t(LineBreak)],
+            // main
+            vec![t(ScopeStart), t(Repeat), Id(0), t(LineBreak), t(Quote)],
+            increments(10),
+            vec![
+                t(Quote),
+                t(LineBreak),
+                t(Zero),
+                t(Decrement),
+                Id(0),
+                t(LineBreak),
+                Id(0),
+                Id(1),
+                t(LineBreak),
+                t(ScopeEnd),
+                t(ScopeStart),
+                Id(1),
+                t(LineBreak),
+                t(Quote),
+            ],
+            increments(9),
+            vec![t(Quote), t(LineBreak), t(ScopeEnd)],
+        ]
+        .concat();
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn cfront_call() {
+        let test = translate(
+            "
+long a;
+
+long f(long b, long c) {
+    a = c;
+    return b;
+}
+
+int main(void) {
+    a = f(1, a);
+    f(0, 0);
+    return 0;
+}
+",
+        )
+        .unwrap();
+        let expected = vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            // f is declared before its parameters
+            t(ScopeStart),
+            t(Access),
+            Id(1),
+            t(Access),
+            Id(2),
+            t(Access),
+            Id(3),
+            t(LineBreak),
+            Id(3),
+            Id(0),
+            t(LineBreak),
+            Id(2),
+            Id(1),
+            t(LineBreak),
+            t(ScopeEnd),
+            // a = f(1, a)
+            Id(1),
+            t(Zero),
+            t(Increment),
+            Id(0),
+            Id(0),
+            t(LineBreak),
+            Id(1),
+            t(Zero),
+            t(Zero),
+            t(LineBreak),
+        ];
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn cfront_input() {
+        let test = translate(
+            "
+int main(void) {
+    long c = getchar() - 1;
+    getchar();
+    return 0;
+}
+",
+        )
+        .unwrap();
+        let expected = vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(Input),
+            t(Decrement),
+            t(Zero),
+            t(Increment),
+            Id(0),
+            t(LineBreak),
+            t(Input),
+            t(LineBreak),
+        ];
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn cfront_operators() {
+        let test = translate(
+            "
+int main(void) {
+    long a = 2, b;
+    b = a + a - 1;
+    a++;
+    b -= a;
+    return 0;
+}
+",
+        )
+        .unwrap();
+        let expected = vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            t(Zero),
+            t(Increment),
+            t(Increment),
+            Id(0),
+            t(LineBreak),
+            Id(0),
+            t(Increment),
+            Id(0),
+            t(Decrement),
+            t(Zero),
+            t(Increment),
+            Id(1),
+            t(LineBreak),
+            Id(0),
+            t(Increment),
+            t(LineBreak),
+            Id(1),
+            t(Decrement),
+            Id(0),
+            Id(1),
+            t(LineBreak),
+        ];
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn cfront_errors() {
+        let test = [
+            translate("int main(void) { a = 1; }"),
+            translate("int main(void) { printf(\"%d\"); }"),
+            translate("int main(void) { return 0; putchar('1'); }"),
+            translate("void f(void) {\nf();\n}"),
+            translate("void f(int a) {}\nint main(void) {\nf();\n}"),
+            translate("long a;\nint main(void) { a(); }"),
+            translate("long a;\nint main(void) {\na = 1 + a;\n}"),
+            translate("int main(void) {\nif (getchar()) {}\n}"),
+            translate("long a;\nint main(void) {\nif (a - 1) {}\n}"),
+        ];
+        let expected = [
+            Err("`a` is undeclared at line 1".to_string()),
+            Err("only plain strings can be printed at line 1".to_string()),
+            Err("return is only supported at the end of a function at line 1".to_string()),
+            Err("recursion isnt supported at line 2".to_string()),
+            Err("`f` takes 1 arguments but got 0 at line 3".to_string()),
+            Err("`a` isnt a function at line 2".to_string()),
+            Err("literals cant be on the left of an operator at line 3".to_string()),
+            Err("if conditions can only be a literal or a variable at line 2".to_string()),
+            Err("if conditions can only be a literal or a variable at line 3".to_string()),
+        ];
+
+        assert_eq!(test, expected);
+    }
+}
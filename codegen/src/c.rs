@@ -0,0 +1,1048 @@
+use ir::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::{fs, io};
+
+// translates the ir into c written like it would be by hand, so cfront can read it back as long
+// as the program is in its subset
+// - variables are global `long`s, or `const char *`s once a string is stored into them
+// - loops and ifs are rebuilt into `while` and `if` from the blocks ir::lower makes for them, so
+//   the ir has to come from there
+// - functions are c functions whose parameters are the variables the arguments are stored into
+//   and whose return value is the variable naming them, unless another function uses those
+// - registers are inlined into the expressions using them and only become temporaries when a
+//   side effect before their use would change them
+// - quotes are printed with `putchar` and `printf`, stdin reads as zero once its empty
+
+// the c expression in a register, until it is used
+#[derive(Debug, Clone, Default)]
+struct Value {
+    c: String,
+    string: bool,           // if its a `const char *`
+    quote: Option<String>,  // the text of a quote, printed without a variable
+    reads: BTreeSet<usize>, // variables it reads
+    effects: bool,          // if it calls a function or reads stdin
+    compound: bool,         // if it needs parentheses on the right of an operator
+}
+
+impl Value {
+    fn literal(c: &str) -> Self {
+        Value {
+            c: c.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn variable(id: usize, string: bool) -> Self {
+        Value {
+            c: format!("v{id}"),
+            string,
+            reads: BTreeSet::from([id]),
+            ..Default::default()
+        }
+    }
+}
+
+// what a function returns
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Returns {
+    Nothing,
+    Variable(usize), // the local variable naming it
+    Value(usize),    // the one value stored into the variable naming it, as the last thing it does
+}
+
+struct Transpiler<'a> {
+    module: &'a Module,
+    indices: HashMap<usize, usize>, // index of every function by its name
+    strings: HashSet<usize>,        // variables holding strings
+    locals: HashMap<usize, usize>,  // parameters and names only their function uses, by its index
+    touches: Vec<BTreeSet<usize>>,  // variables each function uses, including in its calls
+    temporaries: usize,
+    input: bool, // whether stdin is read
+}
+
+// the variable an instruction reads or writes
+fn accessed(instruction: &Instruction) -> Option<usize> {
+    match *instruction {
+        Instruction::Load { variable, .. } | Instruction::Store { variable, .. } => Some(variable),
+        _ => None,
+    }
+}
+
+fn instructions(function: &Function) -> impl Iterator<Item = &Instruction> {
+    function.blocks.iter().flat_map(|block| &block.instructions)
+}
+
+impl<'a> Transpiler<'a> {
+    fn new(module: &'a Module) -> Self {
+        let indices: HashMap<usize, usize> = (module.functions.iter().enumerate())
+            .filter_map(|(i, function)| Some((function.name?, i)))
+            .collect();
+
+        // a string stored into a variable makes it a string, loading it does the same to the register
+        let mut strings = HashSet::new();
+        loop {
+            let before = strings.len();
+            for function in &module.functions {
+                let mut registers = HashSet::new();
+                for instruction in instructions(function) {
+                    match *instruction {
+                        Instruction::String { dst, .. } => {
+                            registers.insert(dst);
+                        }
+                        Instruction::Load { dst, variable } if strings.contains(&variable) => {
+                            registers.insert(dst);
+                        }
+                        Instruction::Store { variable, src } if registers.contains(&src) => {
+                            strings.insert(variable);
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            if strings.len() == before {
+                break;
+            }
+        }
+
+        // other functions only use them to call it, by storing the arguments and loading the result
+        let mut locals = HashMap::new();
+        for (i, function) in module.functions.iter().enumerate() {
+            let Some(name) = function.name else {
+                continue;
+            };
+            for &id in std::iter::once(&name).chain(&function.parameters) {
+                let local = (module.functions.iter().enumerate())
+                    .filter(|&(j, other)| {
+                        j != i && instructions(other).any(|x| accessed(x) == Some(id))
+                    })
+                    .all(|(_, other)| {
+                        instructions(other).any(|x| *x == Instruction::Call { function: name })
+                    });
+                if local {
+                    locals.insert(id, i);
+                }
+            }
+        }
+
+        let mut touches: Vec<BTreeSet<usize>> = (module.functions.iter())
+            .map(|function| instructions(function).filter_map(accessed).collect())
+            .collect();
+        loop {
+            let mut changed = false;
+            for (i, function) in module.functions.iter().enumerate() {
+                for instruction in instructions(function) {
+                    if let Instruction::Call { function } = instruction {
+                        let called = touches[indices[function]].clone();
+                        let before = touches[i].len();
+                        touches[i].extend(called);
+                        changed |= touches[i].len() != before;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        Self {
+            module,
+            indices,
+            strings,
+            locals,
+            touches,
+            temporaries: 0,
+            input: false,
+        }
+    }
+
+    fn returns(&self, index: usize) -> Returns {
+        let function = &self.module.functions[index];
+        let Some(name) = function
+            .name
+            .filter(|name| self.locals.get(name) == Some(&index))
+        else {
+            return Returns::Nothing;
+        };
+        let count = |f: fn(&Instruction, usize) -> bool| {
+            instructions(function).filter(|x| f(x, name)).count()
+        };
+        let stores =
+            count(|x, name| matches!(*x, Instruction::Store { variable, .. } if variable == name));
+        let loads =
+            count(|x, name| matches!(*x, Instruction::Load { variable, .. } if variable == name));
+        // the last block lowered is the only one returning
+        let last = (function.blocks.iter())
+            .find(|block| block.terminator == Terminator::Return)
+            .and_then(|block| block.instructions.last());
+
+        match (stores, loads, last) {
+            (0, _, _) => Returns::Nothing,
+            (1, 0, Some(&Instruction::Store { variable, .. })) if variable == name => {
+                Returns::Value(name)
+            }
+            _ => Returns::Variable(name),
+        }
+    }
+
+    // parameters of the function at `index` that are c parameters
+    fn parameters(&self, index: usize) -> Vec<usize> {
+        (self.module.functions[index].parameters.iter())
+            .filter(|parameter| self.locals.get(parameter) == Some(&index))
+            .copied()
+            .collect()
+    }
+
+    fn kind(&self, id: usize) -> &'static str {
+        match self.strings.contains(&id) {
+            true => "const char *",
+            false => "long ",
+        }
+    }
+
+    fn gen_function(&mut self, index: usize) -> String {
+        let function = &self.module.functions[index];
+        let returns = self.returns(index);
+        let mut c = match function.name {
+            Some(name) => {
+                let kind = match returns {
+                    Returns::Nothing => "void ",
+                    _ => self.kind(name),
+                };
+                let parameters: Vec<String> = (self.parameters(index).into_iter())
+                    .map(|id| format!("{}v{id}", self.kind(id)))
+                    .collect();
+                let parameters = match parameters.is_empty() {
+                    true => "void".to_string(),
+                    false => parameters.join(", "),
+                };
+                format!("{kind}f{name}({parameters}) {{\n")
+            }
+            None => "int main(void) {\n".to_string(),
+        };
+
+        let mut writer = Writer::new(self, index);
+        if let Returns::Variable(name) = returns {
+            let zero = zero(writer.transpiler.strings.contains(&name));
+            writer.line(&format!(
+                "{}v{name} = {zero};",
+                writer.transpiler.kind(name)
+            ));
+        }
+        writer.gen_blocks(0, None);
+        match (function.name, returns) {
+            (None, _) => writer.line("return 0;"),
+            (_, Returns::Variable(name)) => writer.line(&format!("return v{name};")),
+            _ => (),
+        }
+
+        c += &writer.c;
+        c + "}\n"
+    }
+
+    fn finish(self, functions: String) -> String {
+        let mut c = String::from("#include <stdio.h>\n\n");
+
+        // a macro since cfront reads `getchar` and skips preprocessor lines
+        if self.input {
+            c += "// stdin reads as zero once its empty, after what was printed is shown\n";
+            c += "int input;\n";
+            c += "#define getchar() (fflush(stdout), (input = (getchar)()) == EOF ? 0 : input)\n\n";
+        }
+
+        let globals: BTreeSet<usize> = (self.module.functions.iter())
+            .flat_map(|function| instructions(function).filter_map(accessed))
+            .filter(|id| !self.locals.contains_key(id))
+            .collect();
+        let (strings, ints): (Vec<usize>, Vec<usize>) = globals
+            .into_iter()
+            .partition(|id| self.strings.contains(id));
+        if !ints.is_empty() {
+            let ints: Vec<String> = ints.iter().map(|id| format!("v{id}")).collect();
+            c += &format!("long {};\n", ints.join(", "));
+        }
+        if !strings.is_empty() {
+            let strings: Vec<String> = strings.iter().map(|id| format!("*v{id} = \"\"")).collect();
+            c += &format!("const char {};\n", strings.join(", "));
+        }
+        if c.ends_with(";\n") {
+            c += "\n";
+        }
+
+        c + &functions
+    }
+}
+
+// writes the body of a function
+struct Writer<'a, 'b> {
+    transpiler: &'b mut Transpiler<'a>,
+    function: &'a Function,
+    index: usize,
+    uses: Vec<usize>,                  // how many more times each register is used
+    values: BTreeMap<Register, Value>, // registers that are still used
+    arguments: BTreeMap<usize, Value>, // values stored into the parameters of the next call
+    c: String,
+    depth: usize,
+}
+
+impl<'a, 'b> Writer<'a, 'b> {
+    fn new(transpiler: &'b mut Transpiler<'a>, index: usize) -> Self {
+        let function = &transpiler.module.functions[index];
+        let mut uses = vec![0; function.registers];
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                match *instruction {
+                    Instruction::Store { src, .. } | Instruction::Print { src } => uses[src] += 1,
+                    Instruction::Add { left, right, .. }
+                    | Instruction::Subtract { left, right, .. } => {
+                        uses[left] += 1;
+                        uses[right] += 1;
+                    }
+                    _ => (),
+                }
+            }
+            if let Terminator::Branch { condition, .. } = block.terminator {
+                uses[condition] += 1;
+            }
+        }
+
+        Self {
+            transpiler,
+            function,
+            index,
+            uses,
+            values: BTreeMap::new(),
+            arguments: BTreeMap::new(),
+            c: String::new(),
+            depth: 1,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        self.c += &"    ".repeat(self.depth);
+        self.c += line;
+        self.c += "\n";
+    }
+
+    fn define(&mut self, register: Register, value: Value) {
+        if self.uses[register] != 0 {
+            self.values.insert(register, value);
+        } else if value.effects {
+            self.line(&format!("{};", value.c));
+        }
+    }
+
+    fn take(&mut self, register: Register) -> Value {
+        self.uses[register] -= 1;
+        match self.uses[register] {
+            0 => self.values.remove(&register),
+            _ => self.values.get(&register).cloned(),
+        }
+        .unwrap_or_default()
+    }
+
+    // turns the values `f` matches into temporaries, so they keep what they are now
+    fn settle(&mut self, f: impl Fn(&Value) -> bool) {
+        let registers: Vec<Register> = (self.values.iter())
+            .filter(|(_, value)| f(value))
+            .map(|(&register, _)| register)
+            .collect();
+        for register in registers {
+            let value = self.temporary(self.values[&register].clone());
+            self.values.insert(register, value);
+        }
+
+        let parameters: Vec<usize> = (self.arguments.iter())
+            .filter(|(_, value)| f(value))
+            .map(|(&parameter, _)| parameter)
+            .collect();
+        for parameter in parameters {
+            let value = self.temporary(self.arguments[&parameter].clone());
+            self.arguments.insert(parameter, value);
+        }
+    }
+
+    fn temporary(&mut self, value: Value) -> Value {
+        let name = format!("t{}", self.transpiler.temporaries);
+        self.transpiler.temporaries += 1;
+        let kind = match value.string {
+            true => "const char *",
+            false => "long ",
+        };
+        self.line(&format!("{kind}{name} = {};", value.c));
+
+        Value {
+            c: name,
+            string: value.string,
+            quote: value.quote,
+            ..Default::default()
+        }
+    }
+
+    // writes the blocks from `id` on until one jumps to `end`
+    fn gen_blocks(&mut self, mut id: BlockId, end: Option<BlockId>) {
+        loop {
+            let block = &self.function.blocks[id];
+            self.gen_block(block);
+            match block.terminator {
+                Terminator::Return => return,
+                Terminator::Jump(next) if Some(next) == end => return,
+                // only loops jump anywhere else, to their condition
+                Terminator::Jump(header) => match self.function.blocks[header].terminator {
+                    Terminator::Branch {
+                        condition,
+                        then,
+                        otherwise,
+                    } => {
+                        self.gen_loop(header, condition, then);
+                        id = otherwise;
+                    }
+                    _ => id = header,
+                },
+                Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    let condition = self.take(condition);
+                    self.settle(|value| value.effects || !value.reads.is_empty());
+                    self.line(&format!("if ({}) {{", condition.c));
+                    self.depth += 1;
+                    self.gen_blocks(then, Some(otherwise));
+                    self.depth -= 1;
+                    self.line("}");
+                    id = otherwise;
+                }
+            }
+        }
+    }
+
+    // a condition with statements is written before the loop and again at the end of its body
+    fn gen_loop(&mut self, header: BlockId, condition: Register, body: BlockId) {
+        self.settle(|value| value.effects || !value.reads.is_empty());
+        let outer = std::mem::take(&mut self.c);
+        self.gen_block(&self.function.blocks[header]);
+        let condition = self.take(condition);
+        let statements = std::mem::replace(&mut self.c, outer);
+
+        self.c += &statements;
+        self.line(&format!("while ({}) {{", condition.c));
+        self.depth += 1;
+        self.gen_blocks(body, Some(header));
+        for statement in statements.lines() {
+            // temporaries are only declared the first time
+            let statement = statement.trim_start();
+            let statement = (statement.strip_prefix("long "))
+                .or_else(|| statement.strip_prefix("const char *"))
+                .unwrap_or(statement);
+            self.line(statement);
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn gen_block(&mut self, block: &Block) {
+        let mut i = 0;
+        while i < block.instructions.len() {
+            i += self.gen_instruction(&block.instructions[i..]);
+        }
+    }
+
+    // writes the first instruction, returning how many it took
+    fn gen_instruction(&mut self, instructions: &[Instruction]) -> usize {
+        use Instruction::*;
+
+        match instructions[0] {
+            Const { dst, value } => self.define(dst, Value::literal(&value.to_string())),
+            String { dst, ref string } => self.define(
+                dst,
+                Value {
+                    c: string_lit(string),
+                    string: true,
+                    quote: Some(string.clone()),
+                    ..Default::default()
+                },
+            ),
+            Load { dst, variable } => {
+                let string = self.transpiler.strings.contains(&variable);
+                self.define(dst, Value::variable(variable, string));
+            }
+            Store { variable, src } => self.gen_store(variable, src),
+            Add { dst, left, right } => self.gen_operator(dst, left, '+', right),
+            Subtract { dst, left, right } => self.gen_operator(dst, left, '-', right),
+            Input { dst } => {
+                self.transpiler.input = true;
+                self.settle(|value| value.effects);
+                let value = Value {
+                    c: "getchar()".to_string(),
+                    effects: true,
+                    ..Default::default()
+                };
+                self.define(dst, value);
+            }
+            Print { src } => {
+                let value = self.take(src);
+                self.settle(|value| value.effects);
+                match value.quote {
+                    Some(quote) => self.gen_quote(&quote),
+                    None => self.line(&format!("fputs({}, stdout);", value.c)),
+                }
+            }
+            Call { function } => return self.gen_call(function, instructions),
+        }
+        1
+    }
+
+    fn gen_store(&mut self, variable: usize, src: Register) {
+        let value = self.take(src);
+        match self.transpiler.locals.get(&variable) {
+            // arguments are passed to the call after them
+            Some(&function) if function != self.index => {
+                if self.transpiler.module.functions[function]
+                    .parameters
+                    .contains(&variable)
+                {
+                    self.arguments.insert(variable, value);
+                }
+                // the result is zeroed by the function itself
+                return;
+            }
+            _ => (),
+        }
+
+        // the value itself is stored before anything else changes
+        let kept = self.values.remove(&src);
+        self.settle(|value| value.reads.contains(&variable));
+        let c = match self.transpiler.strings.contains(&variable) && !value.string {
+            true => zero(true).to_string(),
+            false => value.c.clone(),
+        };
+        match self.transpiler.returns(self.index) {
+            Returns::Value(name) if name == variable => self.line(&format!("return {c};")),
+            _ => self.line(&format!("v{variable} = {c};")),
+        }
+
+        // storing it again copies the variable instead of evaluating it again
+        if kept.is_some() {
+            let value = match value.reads.is_empty() && !value.effects {
+                true => value,
+                false => Value::variable(variable, value.string),
+            };
+            self.values.insert(src, value);
+        }
+    }
+
+    fn gen_operator(&mut self, dst: Register, left: Register, operator: char, right: Register) {
+        let (left, right) = (self.take(left), self.take(right));
+        // adding a negative literal is subtracting it
+        let negative = (right.reads.is_empty() && !right.compound)
+            .then(|| right.c.strip_prefix('-'))
+            .flatten();
+        let c = match (negative, right.compound) {
+            (Some(literal), _) if operator == '+' => format!("{} - {literal}", left.c),
+            (Some(literal), _) => format!("{} + {literal}", left.c),
+            (None, true) => format!("{} {operator} ({})", left.c, right.c),
+            (None, false) => format!("{} {operator} {}", left.c, right.c),
+        };
+
+        let value = Value {
+            c,
+            reads: left.reads.union(&right.reads).copied().collect(),
+            effects: left.effects || right.effects,
+            compound: true,
+            ..Default::default()
+        };
+        self.define(dst, value);
+    }
+
+    // the call and the load of its result after it
+    fn gen_call(&mut self, function: usize, instructions: &[Instruction]) -> usize {
+        let index = self.transpiler.indices[&function];
+        let arguments: Vec<String> = (self.transpiler.parameters(index).iter())
+            .filter_map(|parameter| self.arguments.remove(parameter))
+            .map(|argument| argument.c)
+            .collect();
+        let touches = self.transpiler.touches[index].clone();
+        self.settle(|value| value.effects || !value.reads.is_disjoint(&touches));
+        let c = format!("f{function}({})", arguments.join(", "));
+
+        let result = match instructions.get(1) {
+            Some(&Instruction::Load { dst, variable })
+                if variable == function
+                    && self.transpiler.locals.get(&function) == Some(&index) =>
+            {
+                dst
+            }
+            _ => {
+                self.line(&format!("{c};"));
+                return 1;
+            }
+        };
+        let string = self.transpiler.strings.contains(&function);
+        if self.transpiler.returns(index) == Returns::Nothing {
+            self.line(&format!("{c};"));
+            self.define(result, Value::literal(zero(string)));
+        } else {
+            let value = Value {
+                c,
+                string,
+                reads: touches,
+                effects: true,
+                ..Default::default()
+            };
+            self.define(result, value);
+        }
+        2
+    }
+
+    fn gen_quote(&mut self, quote: &str) {
+        match quote.as_bytes() {
+            [] => (),
+            &[byte] => self.line(&format!("putchar({});", char_lit(byte))),
+            bytes if bytes.contains(&0) => self.line(&format!(
+                "fwrite({}, 1, {}, stdout);",
+                string_lit(quote),
+                bytes.len()
+            )),
+            _ if quote.contains('%') => {
+                self.line(&format!("fputs({}, stdout);", string_lit(quote)))
+            }
+            _ => self.line(&format!("printf({});", string_lit(quote))),
+        }
+    }
+}
+
+// what a variable holds before anything is stored into it
+fn zero(string: bool) -> &'static str {
+    match string {
+        true => "\"\"",
+        false => "0",
+    }
+}
+
+// a byte escaped for a c literal, bytes that arent printable are octal escapes
+fn escape(byte: u8, quote: u8) -> String {
+    match byte {
+        b'\\' => "\\\\".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        _ if byte == quote => format!("\\{}", byte as char),
+        b' '..=b'~' => (byte as char).to_string(),
+        _ => format!("\\{byte:03o}"),
+    }
+}
+
+fn char_lit(byte: u8) -> String {
+    format!("'{}'", escape(byte, b'\''))
+}
+
+fn string_lit(string: &str) -> String {
+    let bytes: String = string.bytes().map(|byte| escape(byte, b'"')).collect();
+    format!("\"{bytes}\"")
+}
+
+fn transpile(module: &Module) -> String {
+    let mut transpiler = Transpiler::new(module);
+    // functions are lowered after the functions they call, main is written last
+    let mut functions = String::new();
+    for index in (1..module.functions.len()).chain([0]) {
+        functions += &transpiler.gen_function(index);
+        if index != 0 {
+            functions += "\n";
+        }
+    }
+    transpiler.finish(functions)
+}
+
+// outputs the c source from the ir to a file
+// propogates fs::write fails
+pub fn generate(module: &Module, out: &str) -> io::Result<()> {
+    fs::write(out, transpile(module))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::node::build::{boxed, scope, source_image, spanned, statement, variable};
+    use parser::{node, node::Expression::*, SymbolType};
+
+    #[test]
+    fn c_transpile() {
+        let program = node::Program {
+            statements: vec![
+                statement(vec![IntLit(2), variable(0), variable(1)]),
+                statement(vec![scope(
+                    node::ScopeType::Loop,
+                    vec![variable(0)],
+                    vec![
+                        statement(vec![StringLit("'a".to_string())]),
+                        statement(vec![IntLit(0), variable(0)]),
+                    ],
+                )]),
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![variable(2)],
+                    vec![statement(vec![StringLit("\n".to_string())])],
+                )]),
+            ],
+        };
+
+        let test = transpile(&ir::lower(&program));
+        let expected = "\
+#include <stdio.h>
+
+long v0, v1;
+
+void f2(void) {
+    putchar('\\n');
+}
+
+int main(void) {
+    v0 = 2;
+    v1 = 2;
+    while (v0) {
+        printf(\"'a\");
+        v0 = 0;
+    }
+    return 0;
+}
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn c_strings() {
+        // quotes are printed the simplest way that prints all of them
+        let string = |id| Variable((id, SymbolType::String));
+        let quote = |quote: &str| statement(vec![StringLit(quote.to_string())]);
+        let program = node::Program {
+            statements: vec![
+                statement(vec![StringLit("\"\u{1}".to_string()), string(0)]),
+                statement(vec![string(0)]),
+                quote(""),
+                quote("\t"),
+                quote("100%"),
+                quote("a\0b"),
+            ],
+        };
+
+        let test = transpile(&ir::lower(&program));
+        let expected = "\
+#include <stdio.h>
+
+const char *v0 = \"\";
+
+int main(void) {
+    v0 = \"\\\"\\001\";
+    fputs(v0, stdout);
+    putchar('\\t');
+    fputs(\"100%\", stdout);
+    fwrite(\"a\\000b\", 1, 3, stdout);
+    return 0;
+}
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn c_operators() {
+        let program = node::Program {
+            statements: vec![
+                statement(vec![Assign((
+                    0,
+                    boxed(BinOp((
+                        boxed(variable(0)),
+                        node::Operator::Add,
+                        boxed(IntLit(-1)),
+                    ))),
+                ))]),
+                statement(vec![
+                    BinOp((
+                        boxed(variable(0)),
+                        node::Operator::Subtract,
+                        boxed(BinOp((
+                            boxed(variable(1)),
+                            node::Operator::Add,
+                            boxed(IntLit(1)),
+                        ))),
+                    )),
+                    variable(1),
+                    variable(2),
+                ]),
+            ],
+        };
+
+        let test = transpile(&ir::lower(&program));
+        let expected = "\
+#include <stdio.h>
+
+long v0, v1, v2;
+
+int main(void) {
+    v0 = v0 - 1;
+    v1 = v0 - (v1 + 1);
+    v2 = v1;
+    return 0;
+}
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn c_call() {
+        // f0 returns its parameter and f3 nothing
+        // f7 uses the parameter of f5 so it stays global and is stored before the call
+        let program = node::Program {
+            statements: vec![
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![variable(0), variable(1)],
+                    vec![statement(vec![variable(1), variable(0)])],
+                )]),
+                statement(vec![Call((0, vec![spanned(IntLit(4))])), variable(2)]),
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![variable(3)],
+                    vec![statement(vec![StringLit("b".to_string())])],
+                )]),
+                statement(vec![Call((3, Vec::new())), variable(4)]),
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![variable(5), variable(6)],
+                    vec![
+                        statement(vec![scope(
+                            node::ScopeType::Function,
+                            vec![variable(7)],
+                            vec![statement(vec![variable(6), variable(7)])],
+                        )]),
+                        statement(vec![Call((7, Vec::new())), variable(5)]),
+                    ],
+                )]),
+                statement(vec![Call((5, vec![spanned(IntLit(1))])), variable(8)]),
+            ],
+        };
+
+        let test = transpile(&ir::lower(&program));
+        let expected = "\
+#include <stdio.h>
+
+long v2, v4, v6, v8;
+
+long f0(long v1) {
+    return v1;
+}
+
+void f3(void) {
+    putchar('b');
+}
+
+long f7(void) {
+    return v6;
+}
+
+long f5(void) {
+    return f7();
+}
+
+int main(void) {
+    v2 = f0(4);
+    f3();
+    v4 = 0;
+    v6 = 1;
+    v8 = f5();
+    return 0;
+}
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn c_side_effects() {
+        // values are only kept in a temporary when something changes them before they are used
+        let program = node::Program {
+            statements: vec![
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![variable(0)],
+                    vec![statement(vec![IntLit(1), variable(1)])],
+                )]),
+                statement(vec![
+                    BinOp((boxed(Input), node::Operator::Subtract, boxed(Input))),
+                    variable(2),
+                ]),
+                statement(vec![
+                    BinOp((
+                        boxed(variable(1)),
+                        node::Operator::Add,
+                        boxed(Call((0, Vec::new()))),
+                    )),
+                    variable(2),
+                ]),
+                statement(vec![
+                    variable(1),
+                    scope(
+                        node::ScopeType::Loop,
+                        vec![variable(1)],
+                        vec![statement(vec![IntLit(0), variable(1)])],
+                    ),
+                    variable(2),
+                ]),
+            ],
+        };
+
+        let test = transpile(&ir::lower(&program));
+        let expected = "\
+#include <stdio.h>
+
+// stdin reads as zero once its empty, after what was printed is shown
+int input;
+#define getchar() (fflush(stdout), (input = (getchar)()) == EOF ? 0 : input)
+
+long v1, v2;
+
+void f0(void) {
+    v1 = 1;
+}
+
+int main(void) {
+    long t0 = getchar();
+    v2 = t0 - getchar();
+    long t1 = v1;
+    f0();
+    v2 = t1 + 0;
+    long t2 = v1;
+    while (v1) {
+        v1 = 0;
+    }
+    v2 = t2;
+    return 0;
+}
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn c_repeat() {
+        // conditions with statements are written before the loop and at the end of its body
+        let test = [
+            transpile(&ir::lower(&source_image("repeat.png"))),
+            transpile(&ir::lower(&source_image("repeat_input.png"))),
+        ];
+        let main = |body: &str| format!("int main(void) {{\n{body}    return 0;\n}}\n");
+        let expected = [
+            main(
+                "    v0 = 3;
+    while (v0) {
+        putchar('\\n');
+        v0 = v0 - 1;
+    }
+",
+            ),
+            main(
+                "    v0 = getchar();
+    while (v0) {
+        putchar('\\n');
+        v0 = getchar();
+    }
+",
+            ),
+        ];
+
+        for (test, expected) in test.iter().zip(expected) {
+            assert!(test.ends_with(&expected), "{test}");
+        }
+    }
+
+    #[test]
+    fn c_input() {
+        let program = node::Program {
+            statements: vec![statement(vec![Input, variable(0)]), statement(vec![Input])],
+        };
+
+        let test = transpile(&ir::lower(&program));
+        let expected = "\
+#include <stdio.h>
+
+// stdin reads as zero once its empty, after what was printed is shown
+int input;
+#define getchar() (fflush(stdout), (input = (getchar)()) == EOF ? 0 : input)
+
+long v0;
+
+int main(void) {
+    v0 = getchar();
+    getchar();
+    return 0;
+}
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn c_round_trip() {
+        // the c read back by cfront runs the same as the program it was written from
+        fn read(c: &str) -> Module {
+            let lexemes = cfront::translate(c).unwrap();
+            let mut tokens = lexemes.into_iter().map(spanned).collect();
+            let mut program = parser::parse(&mut tokens).unwrap();
+            assert!(parser::analyse(&mut program).is_empty());
+            ir::lower(&program)
+        }
+        fn run(module: &Module, input: &str) -> Vec<u8> {
+            let mut out = Vec::new();
+            interpreter::run_with(module, input.as_bytes(), &mut out).unwrap();
+            out
+        }
+
+        let source = read(
+            "
+long n;
+long total;
+
+long add(long a, long b) {
+    return a + b;
+}
+
+void star(long on) {
+    if (on) {
+        putchar('*');
+    }
+}
+
+int main(void) {
+    n = getchar() - 48;
+    while (n) {
+        total = add(total, n);
+        star(n);
+        n = n - 1;
+    }
+    printf(\"done\\n\");
+    while (total) {
+        putchar('.');
+        total = total - 1;
+    }
+    return 0;
+}
+",
+        );
+        let modules = [
+            source,
+            ir::lower(&source_image("repeat.png")),
+            ir::lower(&source_image("repeat_input.png")),
+        ];
+
+        for module in &modules {
+            let c = transpile(module);
+            let read = read(&c);
+            for input in ["3", "0", "5ab"] {
+                assert_eq!(run(&read, input), run(module, input), "{c}");
+            }
+        }
+        assert_eq!(run(&modules[0], "3"), b"***done\n......");
+    }
+}
@@ -1,20 +1,598 @@
-use parser::*;
+pub mod c;
+
+use ir::*;
+use std::collections::HashSet;
 use std::{fs, io};
 
-// outputes the assembly from the ast to a file
+// every register gets a stack slot in its functions frame
+// variables live in .bss
+// strings are pointers to their length followed by their bytes in .data, null is the empty string
+
+struct Generator {
+    text: String,
+    data: String, // string literals
+    variables: HashSet<usize>,
+    strings: usize,
+    prints: usize, // for labels skipping empty strings
+}
+
+impl Generator {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            data: String::new(),
+            variables: HashSet::new(),
+            strings: 0,
+            prints: 0,
+        }
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        self.text += &format!("\t{instruction}\n");
+    }
+
+    fn emit_label(&mut self, label: &str) {
+        self.text += &format!("{label}:\n");
+    }
+
+    fn gen_function(&mut self, function: &Function) {
+        let entry = function.name.is_none();
+        match function.name {
+            Some(name) => self.emit_label(&format!("fn_{name}")),
+            None => self.emit_label("_start"),
+        }
+
+        // rsp stays 16 byte aligned at every call, functions are entered 8 bytes off and pushing
+        // rbp realigns them, _start is entered aligned and never returns so it doesnt push it
+        let frame = (function.registers * 8).div_ceil(16) * 16;
+        if !entry {
+            self.emit("push rbp");
+        }
+        self.emit("mov rbp, rsp");
+        if frame != 0 {
+            self.emit(&format!("sub rsp, {frame}"));
+        }
+
+        for (id, block) in function.blocks.iter().enumerate() {
+            self.emit_label(&format!(".b{id}"));
+            for instruction in &block.instructions {
+                self.gen_instruction(instruction);
+            }
+            self.gen_terminator(&block.terminator, entry);
+        }
+    }
+
+    fn gen_instruction(&mut self, instruction: &Instruction) {
+        use Instruction::*;
+
+        match instruction {
+            Const { dst, value } => {
+                self.emit(&format!("mov rax, {value}"));
+                self.emit(&format!("mov {}, rax", slot(*dst)));
+            }
+            Load { dst, variable } => {
+                self.variables.insert(*variable);
+                self.emit(&format!("mov rax, [var_{variable}]"));
+                self.emit(&format!("mov {}, rax", slot(*dst)));
+            }
+            Store { variable, src } => {
+                self.variables.insert(*variable);
+                self.emit(&format!("mov rax, {}", slot(*src)));
+                self.emit(&format!("mov [var_{variable}], rax"));
+            }
+            Add { dst, left, right } => {
+                self.emit(&format!("mov rax, {}", slot(*left)));
+                self.emit(&format!("add rax, {}", slot(*right)));
+                self.emit(&format!("mov {}, rax", slot(*dst)));
+            }
+            Subtract { dst, left, right } => {
+                self.emit(&format!("mov rax, {}", slot(*left)));
+                self.emit(&format!("sub rax, {}", slot(*right)));
+                self.emit(&format!("mov {}, rax", slot(*dst)));
+            }
+            String { dst, string } => {
+                let id = self.gen_string(string);
+                self.emit(&format!("mov rax, str_{id}"));
+                self.emit(&format!("mov {}, rax", slot(*dst)));
+            }
+            Input { dst } => self.gen_input(*dst),
+            Print { src } => self.gen_print(*src),
+            Call { function } => self.emit(&format!("call fn_{function}")),
+        }
+    }
+
+    fn gen_string(&mut self, string: &str) -> usize {
+        let id = self.strings;
+        self.strings += 1;
+
+        self.data += &format!("\tstr_{id}: dq {}\n", string.len());
+        if !string.is_empty() {
+            let bytes: Vec<String> = string.bytes().map(|b| b.to_string()).collect();
+            self.data += &format!("\t\tdb {}\n", bytes.join(", "));
+        }
+        id
+    }
+
+    // reads straight into the zeroed slot so it stays zero when nothing is read
+    fn gen_input(&mut self, dst: Register) {
+        self.emit(&format!("mov {}, 0", slot(dst)));
+        self.emit("mov rax, 0"); // read
+        self.emit("mov rdi, 0"); // stdin
+        self.emit(&format!("lea rsi, [{}]", address(dst)));
+        self.emit("mov rdx, 1");
+        self.emit("syscall");
+    }
+
+    fn gen_print(&mut self, src: Register) {
+        let skip = format!(".print_{}", self.prints);
+        self.prints += 1;
+
+        self.emit(&format!("mov rsi, {}", slot(src)));
+        self.emit("test rsi, rsi");
+        self.emit(&format!("jz {skip}"));
+        self.emit("mov rdx, [rsi]"); // length
+        self.emit("add rsi, 8");
+        self.emit("mov rax, 1"); // write
+        self.emit("mov rdi, 1"); // stdout
+        self.emit("syscall");
+        self.emit_label(&skip);
+    }
+
+    fn gen_terminator(&mut self, terminator: &Terminator, entry: bool) {
+        match terminator {
+            Terminator::Jump(block) => self.emit(&format!("jmp .b{block}")),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.emit(&format!("mov rax, {}", slot(*condition)));
+                self.emit("test rax, rax");
+                self.emit(&format!("jnz .b{then}"));
+                self.emit(&format!("jmp .b{otherwise}"));
+            }
+            // returning from the entry point exits the program
+            Terminator::Return if entry => {
+                self.emit("mov rax, 60"); // exit
+                self.emit("xor rdi, rdi");
+                self.emit("syscall");
+            }
+            Terminator::Return => {
+                self.emit("leave");
+                self.emit("ret");
+            }
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut asm: String = Default::default();
+
+        let mut variables: Vec<&usize> = self.variables.iter().collect();
+        variables.sort();
+        asm += "section .bss\n";
+        for id in variables {
+            asm += &format!("\tvar_{id}: resq 1\n");
+        }
+
+        asm += "section .data\n";
+        asm += &self.data;
+
+        asm += "section .text\n";
+        asm += "global _start\n";
+        asm += &self.text;
+
+        asm
+    }
+}
+
+// stack slot of a register
+fn slot(register: Register) -> String {
+    format!("qword [{}]", address(register))
+}
+
+// address of a registers stack slot
+fn address(register: Register) -> String {
+    format!("rbp - {}", (register + 1) * 8)
+}
+
+// the nasm source of the ir
+fn assemble(module: &Module) -> String {
+    let mut generator = Generator::new();
+    for function in &module.functions {
+        generator.gen_function(function);
+    }
+    generator.finish()
+}
+
+// outputes the assembly from the ir to a file
 // propogates fs::write fails
-pub fn generate(program: &node::Program, out: &str) -> io::Result<()> {
-    let mut asm: String = Default::default();
-    asm += "global _start\n";
-    asm += "\t_start:\n";
+pub fn generate(module: &Module, out: &str) -> io::Result<()> {
+    fs::write(out, assemble(module))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::node::build::{scope, source_image, statement, variable};
+    use parser::node::{self, Expression::*};
+
+    // the asm of a program built by hand
+    fn program(statements: Vec<node::Statement>) -> String {
+        assemble(&ir::lower(&node::Program { statements }))
+    }
+
+    #[test]
+    fn asm_variables() {
+        // the literal is stored into both variables
+        let test = program(vec![statement(vec![IntLit(2), variable(0), variable(1)])]);
+        let expected = "\
+section .bss
+\tvar_0: resq 1
+\tvar_1: resq 1
+section .data
+section .text
+global _start
+_start:
+\tmov rbp, rsp
+\tsub rsp, 16
+.b0:
+\tmov rax, 2
+\tmov qword [rbp - 8], rax
+\tmov rax, qword [rbp - 8]
+\tmov [var_0], rax
+\tmov rax, qword [rbp - 8]
+\tmov [var_1], rax
+\tmov rax, 60
+\txor rdi, rdi
+\tsyscall
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn asm_quote() {
+        let test = program(vec![statement(vec![StringLit("a\n".to_string())])]);
+        let expected = "\
+section .bss
+section .data
+\tstr_0: dq 2
+\t\tdb 97, 10
+section .text
+global _start
+_start:
+\tmov rbp, rsp
+\tsub rsp, 16
+.b0:
+\tmov rax, str_0
+\tmov qword [rbp - 8], rax
+\tmov rsi, qword [rbp - 8]
+\ttest rsi, rsi
+\tjz .print_0
+\tmov rdx, [rsi]
+\tadd rsi, 8
+\tmov rax, 1
+\tmov rdi, 1
+\tsyscall
+.print_0:
+\tmov rax, 60
+\txor rdi, rdi
+\tsyscall
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn asm_loop() {
+        // the condition is loaded again before every iteration
+        let test = program(vec![statement(vec![scope(
+            node::ScopeType::Loop,
+            vec![variable(0)],
+            vec![statement(vec![IntLit(0), variable(0)])],
+        )])]);
+        let expected = "\
+section .bss
+\tvar_0: resq 1
+section .data
+section .text
+global _start
+_start:
+\tmov rbp, rsp
+\tsub rsp, 16
+.b0:
+\tjmp .b1
+.b1:
+\tmov rax, [var_0]
+\tmov qword [rbp - 8], rax
+\tmov rax, qword [rbp - 8]
+\ttest rax, rax
+\tjnz .b2
+\tjmp .b3
+.b2:
+\tmov rax, 0
+\tmov qword [rbp - 16], rax
+\tmov rax, qword [rbp - 16]
+\tmov [var_0], rax
+\tjmp .b1
+.b3:
+\tmov rax, 60
+\txor rdi, rdi
+\tsyscall
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn asm_function() {
+        // the body is only run by the call, which returns through the functions name
+        let test = program(vec![
+            statement(vec![scope(
+                node::ScopeType::Function,
+                vec![variable(0)],
+                vec![statement(vec![IntLit(1), variable(0)])],
+            )]),
+            statement(vec![Call((0, Vec::new())), variable(1)]),
+        ]);
+        let expected = "\
+section .bss
+\tvar_0: resq 1
+\tvar_1: resq 1
+section .data
+section .text
+global _start
+_start:
+\tmov rbp, rsp
+\tsub rsp, 16
+.b0:
+\tmov rax, 0
+\tmov qword [rbp - 8], rax
+\tmov rax, qword [rbp - 8]
+\tmov [var_0], rax
+\tcall fn_0
+\tmov rax, [var_0]
+\tmov qword [rbp - 16], rax
+\tmov rax, qword [rbp - 16]
+\tmov [var_1], rax
+\tmov rax, 60
+\txor rdi, rdi
+\tsyscall
+fn_0:
+\tpush rbp
+\tmov rbp, rsp
+\tsub rsp, 16
+.b0:
+\tmov rax, 1
+\tmov qword [rbp - 8], rax
+\tmov rax, qword [rbp - 8]
+\tmov [var_0], rax
+\tleave
+\tret
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn asm_repeat() {
+        // the signature reads and stores the input again before every iteration
+        let test = assemble(&ir::lower(&source_image("repeat_input.png")));
+        let expected = "\
+section .bss
+\tvar_0: resq 1
+section .data
+\tstr_0: dq 1
+\t\tdb 10
+section .text
+global _start
+_start:
+\tmov rbp, rsp
+\tsub rsp, 16
+.b0:
+\tjmp .b1
+.b1:
+\tmov qword [rbp - 8], 0
+\tmov rax, 0
+\tmov rdi, 0
+\tlea rsi, [rbp - 8]
+\tmov rdx, 1
+\tsyscall
+\tmov rax, qword [rbp - 8]
+\tmov [var_0], rax
+\tmov rax, qword [rbp - 8]
+\ttest rax, rax
+\tjnz .b2
+\tjmp .b3
+.b2:
+\tmov rax, str_0
+\tmov qword [rbp - 16], rax
+\tmov rsi, qword [rbp - 16]
+\ttest rsi, rsi
+\tjz .print_0
+\tmov rdx, [rsi]
+\tadd rsi, 8
+\tmov rax, 1
+\tmov rdi, 1
+\tsyscall
+.print_0:
+\tjmp .b1
+.b3:
+\tmov rax, 60
+\txor rdi, rdi
+\tsyscall
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn asm_instructions() {
+        use Instruction::*;
+
+        let instructions = vec![
+            Const { dst: 0, value: -7 },
+            Store {
+                variable: 3,
+                src: 0,
+            },
+            Load {
+                dst: 1,
+                variable: 3,
+            },
+            Add {
+                dst: 2,
+                left: 0,
+                right: 1,
+            },
+            Subtract {
+                dst: 3,
+                left: 2,
+                right: 0,
+            },
+            String {
+                dst: 4,
+                string: "hi".to_string(),
+            },
+            String {
+                dst: 5,
+                string: "".to_string(),
+            },
+            Print { src: 4 },
+            Input { dst: 6 },
+            Call { function: 3 },
+        ];
+        let module = Module {
+            functions: vec![Function {
+                name: None,
+                parameters: Vec::new(),
+                blocks: vec![Block {
+                    instructions,
+                    terminator: Terminator::Return,
+                }],
+                registers: 7,
+            }],
+        };
+
+        let test = assemble(&module);
+        let expected = "\
+section .bss
+\tvar_3: resq 1
+section .data
+\tstr_0: dq 2
+\t\tdb 104, 105
+\tstr_1: dq 0
+section .text
+global _start
+_start:
+\tmov rbp, rsp
+\tsub rsp, 64
+.b0:
+\tmov rax, -7
+\tmov qword [rbp - 8], rax
+\tmov rax, qword [rbp - 8]
+\tmov [var_3], rax
+\tmov rax, [var_3]
+\tmov qword [rbp - 16], rax
+\tmov rax, qword [rbp - 8]
+\tadd rax, qword [rbp - 16]
+\tmov qword [rbp - 24], rax
+\tmov rax, qword [rbp - 24]
+\tsub rax, qword [rbp - 8]
+\tmov qword [rbp - 32], rax
+\tmov rax, str_0
+\tmov qword [rbp - 40], rax
+\tmov rax, str_1
+\tmov qword [rbp - 48], rax
+\tmov rsi, qword [rbp - 40]
+\ttest rsi, rsi
+\tjz .print_0
+\tmov rdx, [rsi]
+\tadd rsi, 8
+\tmov rax, 1
+\tmov rdi, 1
+\tsyscall
+.print_0:
+\tmov qword [rbp - 56], 0
+\tmov rax, 0
+\tmov rdi, 0
+\tlea rsi, [rbp - 56]
+\tmov rdx, 1
+\tsyscall
+\tcall fn_3
+\tmov rax, 60
+\txor rdi, rdi
+\tsyscall
+";
 
-    asm += "\tmov rax, 60\n";
-    asm += &format!("\tmov rdi, {}\n", 1);
-    asm += "\tsyscall";
+        assert_eq!(test, expected);
+    }
 
-    fs::write(out, asm)?;
+    #[test]
+    fn asm_terminators() {
+        // the frame is rounded up to keep the stack aligned, only functions save rbp and return
+        let module = Module {
+            functions: vec![
+                Function {
+                    name: None,
+                    parameters: Vec::new(),
+                    blocks: vec![
+                        Block {
+                            instructions: Vec::new(),
+                            terminator: Terminator::Jump(1),
+                        },
+                        Block {
+                            instructions: vec![Instruction::Const { dst: 0, value: 1 }],
+                            terminator: Terminator::Branch {
+                                condition: 0,
+                                then: 2,
+                                otherwise: 0,
+                            },
+                        },
+                        Block::default(),
+                    ],
+                    registers: 1,
+                },
+                Function {
+                    name: Some(2),
+                    parameters: Vec::new(),
+                    blocks: vec![Block::default()],
+                    registers: 3,
+                },
+            ],
+        };
 
-    println!("outputted assembly to {out}");
+        let test = assemble(&module);
+        let expected = "\
+section .bss
+section .data
+section .text
+global _start
+_start:
+\tmov rbp, rsp
+\tsub rsp, 16
+.b0:
+\tjmp .b1
+.b1:
+\tmov rax, 1
+\tmov qword [rbp - 8], rax
+\tmov rax, qword [rbp - 8]
+\ttest rax, rax
+\tjnz .b2
+\tjmp .b0
+.b2:
+\tmov rax, 60
+\txor rdi, rdi
+\tsyscall
+fn_2:
+\tpush rbp
+\tmov rbp, rsp
+\tsub rsp, 32
+.b0:
+\tleave
+\tret
+";
 
-    Ok(())
+        assert_eq!(test, expected);
+    }
 }
@@ -0,0 +1,361 @@
+use ir::*;
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+// executes the ir of a program, see the ir crate for the evaluation rules
+struct Interpreter<'a, R: Read, W: Write> {
+    module: &'a Module,
+    input: R,
+    out: W,
+    variables: HashMap<usize, isize>,
+    strings: Vec<&'a str>, // a string is its index here plus one, zero is the empty string
+}
+
+impl<'a, R: Read, W: Write> Interpreter<'a, R, W> {
+    fn new(module: &'a Module, input: R, out: W) -> Self {
+        Self {
+            module,
+            input,
+            out,
+            variables: HashMap::new(),
+            strings: Vec::new(),
+        }
+    }
+
+    fn run_function(&mut self, function: &'a Function) -> io::Result<()> {
+        let mut registers = vec![0; function.registers];
+        let mut block = 0;
+
+        loop {
+            for instruction in &function.blocks[block].instructions {
+                self.run_instruction(instruction, &mut registers)?;
+            }
+
+            block = match function.blocks[block].terminator {
+                Terminator::Jump(next) => next,
+                Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => match registers[condition] {
+                    0 => otherwise,
+                    _ => then,
+                },
+                Terminator::Return => return Ok(()),
+            };
+        }
+    }
+
+    fn run_instruction(
+        &mut self,
+        instruction: &'a Instruction,
+        registers: &mut [isize],
+    ) -> io::Result<()> {
+        use Instruction::*;
+
+        match instruction {
+            Const { dst, value } => registers[*dst] = *value,
+            String { dst, string } => {
+                let index = match self.strings.iter().position(|s| s == string) {
+                    Some(index) => index,
+                    None => {
+                        self.strings.push(string);
+                        self.strings.len() - 1
+                    }
+                };
+                registers[*dst] = index as isize + 1;
+            }
+            Load { dst, variable } => {
+                registers[*dst] = self.variables.get(variable).copied().unwrap_or_default()
+            }
+            Store { variable, src } => {
+                self.variables.insert(*variable, registers[*src]);
+            }
+            // wrapping like the 64 bit registers of the compiled program
+            Add { dst, left, right } => {
+                registers[*dst] = registers[*left].wrapping_add(registers[*right])
+            }
+            Subtract { dst, left, right } => {
+                registers[*dst] = registers[*left].wrapping_sub(registers[*right])
+            }
+            Input { dst } => {
+                // anything asking for input should be seen first
+                self.out.flush()?;
+                let mut byte = [0];
+                registers[*dst] = match self.input.read(&mut byte)? {
+                    0 => 0,
+                    _ => byte[0] as isize,
+                };
+            }
+            Print { src } => {
+                let index = registers[*src] as usize;
+                if let Some(string) = index.checked_sub(1).and_then(|i| self.strings.get(i)) {
+                    write!(self.out, "{string}")?;
+                }
+            }
+            Call { function } => {
+                // unwrap is fine since lowering only calls functions it has generated
+                let function = self.module.function(*function).unwrap();
+                self.run_function(function)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// runs the program reading its input from `input` and writing its output to `out`
+pub fn run_with<R: Read, W: Write>(module: &Module, input: R, out: W) -> io::Result<()> {
+    let mut interpreter = Interpreter::new(module, input, out);
+    interpreter.run_function(module.entry())?;
+    interpreter.out.flush()
+}
+
+// runs the program on stdin and stdout
+pub fn run(module: &Module) -> io::Result<()> {
+    run_with(module, io::stdin().lock(), io::stdout().lock())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::node::build::{boxed, scope, source_image, spanned, statement, variable};
+    use parser::{node, node::Expression::*, SymbolType};
+
+    fn output(statements: Vec<node::Statement>) -> String {
+        let mut out = Vec::new();
+        run_with(
+            &ir::lower(&node::Program { statements }),
+            io::empty(),
+            &mut out,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn interpreter_quote() {
+        let test = output(vec![
+            statement(vec![StringLit("3".to_string())]),
+            statement(vec![StringLit("12".to_string())]),
+        ]);
+        let expected = "312";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn interpreter_strings() {
+        // stored strings arent printed until they are loaded on their own
+        let string = |id| Variable((id, SymbolType::String));
+        let test = output(vec![
+            statement(vec![string(0)]),
+            statement(vec![StringLit("ab".to_string()), string(1)]),
+            statement(vec![string(1), string(0)]),
+            statement(vec![string(0)]),
+            statement(vec![string(1)]),
+        ]);
+        let expected = "abab";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn interpreter_input() {
+        // input is zero once its empty
+        let module = ir::lower(&node::Program {
+            statements: vec![
+                statement(vec![Input, variable(0)]),
+                statement(vec![Input]),
+                statement(vec![Input, variable(1)]),
+                statement(vec![Input, variable(2)]),
+            ],
+        });
+        let mut interpreter = Interpreter::new(&module, &b"abc"[..], io::sink());
+        interpreter.run_function(module.entry()).unwrap();
+
+        assert_eq!(interpreter.variables[&0], 97);
+        assert_eq!(interpreter.variables[&1], 99);
+        assert_eq!(interpreter.variables[&2], 0);
+    }
+
+    #[test]
+    fn interpreter_variables() {
+        let module = ir::lower(&node::Program {
+            statements: vec![
+                statement(vec![variable(0)]),
+                statement(vec![IntLit(4), variable(0)]),
+                statement(vec![variable(0), variable(1)]),
+            ],
+        });
+        let mut interpreter = Interpreter::new(&module, io::empty(), io::sink());
+        interpreter.run_function(module.entry()).unwrap();
+
+        assert_eq!(interpreter.variables[&0], 4);
+        assert_eq!(interpreter.variables[&1], 4);
+    }
+
+    #[test]
+    fn interpreter_loop() {
+        // runs until the body zeroes its condition
+        let test = output(vec![
+            statement(vec![IntLit(1), variable(0)]),
+            statement(vec![scope(
+                node::ScopeType::Loop,
+                vec![variable(0)],
+                vec![
+                    statement(vec![StringLit("a".to_string())]),
+                    statement(vec![IntLit(0), variable(0)]),
+                ],
+            )]),
+            statement(vec![StringLit("b".to_string())]),
+        ]);
+        let expected = "ab";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn interpreter_repeat() {
+        // the signature is evaluated again before every iteration, stopping at zero
+        let run = |source: &str, input: &[u8]| {
+            let mut out = Vec::new();
+            run_with(&ir::lower(&source_image(source)), input, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let test = [
+            run("repeat.png", b""),
+            run("repeat_input.png", b"ab"),
+            run("repeat_input.png", b""),
+        ];
+        let expected = ["\n\n\n", "\n\n", ""];
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn interpreter_if() {
+        // the body runs once if the condition isnt zero
+        let test = output(vec![
+            statement(vec![IntLit(2), variable(0)]),
+            statement(vec![scope(
+                node::ScopeType::If,
+                vec![variable(0)],
+                vec![statement(vec![StringLit("a".to_string())])],
+            )]),
+            statement(vec![scope(
+                node::ScopeType::If,
+                vec![IntLit(0)],
+                vec![statement(vec![StringLit("b".to_string())])],
+            )]),
+        ]);
+        let expected = "a";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn interpreter_operators() {
+        let module = ir::lower(&node::Program {
+            statements: vec![
+                statement(vec![IntLit(5), variable(0)]),
+                statement(vec![Assign((
+                    0,
+                    boxed(BinOp((
+                        boxed(variable(0)),
+                        node::Operator::Add,
+                        boxed(IntLit(1)),
+                    ))),
+                ))]),
+                statement(vec![
+                    BinOp((
+                        boxed(variable(0)),
+                        node::Operator::Subtract,
+                        boxed(IntLit(8)),
+                    )),
+                    variable(1),
+                ]),
+            ],
+        });
+        let mut interpreter = Interpreter::new(&module, io::empty(), io::sink());
+        interpreter.run_function(module.entry()).unwrap();
+
+        assert_eq!(interpreter.variables[&0], 6);
+        assert_eq!(interpreter.variables[&1], -2);
+    }
+
+    #[test]
+    fn interpreter_call() {
+        // the function prints and returns its parameter, the result is passed to it again
+        let module = ir::lower(&node::Program {
+            statements: vec![
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![variable(0), variable(1)],
+                    vec![
+                        statement(vec![StringLit("a".to_string())]),
+                        statement(vec![variable(1), variable(0)]),
+                    ],
+                )]),
+                statement(vec![Call((0, vec![spanned(IntLit(3))])), variable(2)]),
+                statement(vec![Call((0, vec![spanned(variable(2))])), variable(3)]),
+            ],
+        });
+        let mut out = Vec::new();
+        let mut interpreter = Interpreter::new(&module, io::empty(), &mut out);
+        interpreter.run_function(module.entry()).unwrap();
+
+        assert_eq!(interpreter.variables[&2], 3);
+        assert_eq!(interpreter.variables[&3], 3);
+        assert_eq!(out, b"aa");
+    }
+
+    #[test]
+    fn interpreter_call_locals() {
+        // the function declares a local with the same name as a global declared after it
+        let declaration = |id| Declaration((id, SymbolType::Undefined));
+        let mut program = node::Program {
+            statements: vec![
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![declaration(0)],
+                    vec![statement(vec![IntLit(1), declaration(1)])],
+                )]),
+                statement(vec![IntLit(2), declaration(1)]),
+                statement(vec![Call((0, Vec::new()))]),
+            ],
+        };
+        assert!(parser::analyse(&mut program).is_empty());
+        let Declaration((global, _)) = program.statements[1].expressions[1].value else {
+            panic!("expected a declaration");
+        };
+        let module = ir::lower(&program);
+        let mut interpreter = Interpreter::new(&module, io::empty(), io::sink());
+        interpreter.run_function(module.entry()).unwrap();
+
+        assert_eq!(interpreter.variables[&global], 2);
+    }
+
+    #[test]
+    fn interpreter_function() {
+        // function bodies are only run when called
+        let module = ir::lower(&node::Program {
+            statements: vec![statement(vec![scope(
+                node::ScopeType::Function,
+                vec![variable(2)],
+                vec![statement(vec![IntLit(1), variable(0)])],
+            )])],
+        });
+        let mut interpreter = Interpreter::new(&module, io::empty(), io::sink());
+        interpreter.run_function(module.entry()).unwrap();
+
+        assert_eq!(interpreter.variables.get(&0), None);
+
+        interpreter
+            .run_instruction(&Instruction::Call { function: 2 }, &mut [])
+            .unwrap();
+
+        assert_eq!(interpreter.variables.get(&0), Some(&1));
+    }
+}
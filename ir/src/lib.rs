@@ -0,0 +1,621 @@
+use parser::*;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// a flat representation of a program made of functions containing basic blocks
+// every value lives in a virtual register which is only written once
+// variables are global memory addressed by their id
+
+pub type Register = usize;
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Const {
+        dst: Register,
+        value: isize,
+    },
+    // a string constant, strings are handles the backends resolve and zero is the empty string
+    String {
+        dst: Register,
+        string: String,
+    },
+    Load {
+        dst: Register,
+        variable: usize,
+    },
+    Store {
+        variable: usize,
+        src: Register,
+    },
+    Add {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Subtract {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    // reads a byte from stdin, zero once stdin is empty
+    Input {
+        dst: Register,
+    },
+    // writes the string in the register to stdout
+    Print {
+        src: Register,
+    },
+    // calls the function named by the variable id
+    Call {
+        function: usize,
+    },
+}
+
+// how a block is left
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Terminator {
+    Jump(BlockId),
+    // jumps to `then` if the condition is non zero
+    Branch {
+        condition: Register,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    #[default]
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Function {
+    pub name: Option<usize>, // variable id naming the function, None for the entry point
+    pub parameters: Vec<usize>, // variables the arguments of a call are stored into
+    pub blocks: Vec<Block>,  // the first block is where execution begins
+    pub registers: usize,    // amount of registers used
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module {
+    pub functions: Vec<Function>, // the first function is the entry point
+}
+
+impl Module {
+    pub fn entry(&self) -> &Function {
+        &self.functions[0]
+    }
+
+    pub fn function(&self, name: usize) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == Some(name))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        match self {
+            Const { dst, value } => write!(f, "r{dst} = const {value}"),
+            Load { dst, variable } => write!(f, "r{dst} = load v{variable}"),
+            Store { variable, src } => write!(f, "store v{variable}, r{src}"),
+            Add { dst, left, right } => write!(f, "r{dst} = add r{left}, r{right}"),
+            Subtract { dst, left, right } => write!(f, "r{dst} = sub r{left}, r{right}"),
+            String { dst, string } => write!(f, "r{dst} = string {string:?}"),
+            Input { dst } => write!(f, "r{dst} = input"),
+            Print { src } => write!(f, "print r{src}"),
+            Call { function } => write!(f, "call f{function}"),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Jump(block) => write!(f, "jump b{block}"),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => write!(f, "branch r{condition}, b{then}, b{otherwise}"),
+            Terminator::Return => write!(f, "return"),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(name) => {
+                let parameters: Vec<String> =
+                    self.parameters.iter().map(|id| format!("v{id}")).collect();
+                writeln!(f, "function f{name}({}):", parameters.join(", "))?
+            }
+            None => writeln!(f, "function main:")?,
+        }
+
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{id}:")?;
+            for instruction in &block.instructions {
+                writeln!(f, "\t{instruction}")?;
+            }
+            writeln!(f, "\t{}", block.terminator)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{function}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// builds the function that is currently being lowered
+struct Builder {
+    function: Function,
+    current: BlockId,
+}
+
+impl Builder {
+    fn new(name: Option<usize>, parameters: Vec<usize>) -> Self {
+        Self {
+            function: Function {
+                name,
+                parameters,
+                blocks: vec![Default::default()],
+                registers: 0,
+            },
+            current: 0,
+        }
+    }
+
+    fn register(&mut self) -> Register {
+        self.function.registers += 1;
+        self.function.registers - 1
+    }
+
+    fn block(&mut self) -> BlockId {
+        self.function.blocks.push(Default::default());
+        self.function.blocks.len() - 1
+    }
+
+    fn push(&mut self, instruction: Instruction) {
+        self.function.blocks[self.current]
+            .instructions
+            .push(instruction);
+    }
+
+    // terminates the current block and continues in `next`
+    fn terminate(&mut self, terminator: Terminator, next: BlockId) {
+        self.function.blocks[self.current].terminator = terminator;
+        self.current = next;
+    }
+}
+
+// lowers the ast
+// statements are evaluated left to right into a single value:
+// - integer literals load their value
+// - variables store the value if one came before them, otherwise they load their value
+// - quotes set the value to their string
+// - calls store their arguments into the functions parameters, zero its variable and then
+//   load it as the value, so the body returns whatever it stores into its own name
+// - operators and assignments set the value to their result, assignments also store it
+// a string that is still the value at the end of its statement without being stored is printed
+// the value of a scopes signature is its condition (zero is false)
+struct Lowerer {
+    functions: Vec<Function>,
+    parameters: HashMap<usize, Vec<usize>>, // parameters of every function lowered so far
+    returns: HashMap<usize, SymbolType>,    // what every function lowered so far returns
+}
+
+impl Lowerer {
+    fn lower_function(&mut self, name: Option<usize>, body: &node::Program) -> Function {
+        let parameters = name.and_then(|name| self.parameters.get(&name).cloned());
+        let mut builder = Builder::new(name, parameters.unwrap_or_default());
+        self.lower_program(&mut builder, body);
+        builder.function
+    }
+
+    fn lower_program(&mut self, builder: &mut Builder, program: &node::Program) {
+        for statement in &program.statements {
+            self.lower_statement(builder, statement);
+        }
+    }
+
+    // returns the register holding the value of the statement if it has one
+    fn lower_statement(
+        &mut self,
+        builder: &mut Builder,
+        statement: &node::Statement,
+    ) -> Option<Register> {
+        use node::Expression::*;
+
+        let mut value = None;
+        let mut stored = false;
+        let mut kind = SymbolType::Int;
+        for expression in &statement.expressions {
+            match (&expression.value, value) {
+                (Scope(scope), _) => self.lower_scope(builder, scope),
+                (ScopeEnd, _) => (),
+                (Variable((variable, _)) | Declaration((variable, _)), Some(src)) => {
+                    builder.push(Instruction::Store {
+                        variable: *variable,
+                        src,
+                    });
+                    stored = true;
+                }
+                (value_expression, _) => {
+                    value = Some(self.lower_value(builder, value_expression));
+                    kind = self.kind(value_expression);
+                    stored = false;
+                }
+            }
+        }
+
+        if let (Some(src), SymbolType::String, false) = (value, kind, stored) {
+            builder.push(Instruction::Print { src });
+        }
+        value
+    }
+
+    // the type of an expression that is a value, unanalysed variables are integers
+    fn kind(&self, expression: &node::Expression) -> SymbolType {
+        use node::Expression::*;
+
+        match expression {
+            StringLit(_) => SymbolType::String,
+            Variable((_, kind)) | Declaration((_, kind)) => *kind,
+            Call((function, _)) => self
+                .returns
+                .get(function)
+                .copied()
+                .unwrap_or(SymbolType::Int),
+            Assign((_, value)) => self.kind(&value.value),
+            _ => SymbolType::Int,
+        }
+    }
+
+    // the register holding the result of an expression that is a value
+    fn lower_value(&mut self, builder: &mut Builder, expression: &node::Expression) -> Register {
+        use node::Expression::*;
+
+        match expression {
+            StringLit(string) => {
+                let dst = builder.register();
+                builder.push(Instruction::String {
+                    dst,
+                    string: string.clone(),
+                });
+                dst
+            }
+            Variable((variable, _)) | Declaration((variable, _)) => {
+                let dst = builder.register();
+                builder.push(Instruction::Load {
+                    dst,
+                    variable: *variable,
+                });
+                dst
+            }
+            Call((function, arguments)) => {
+                let arguments: Vec<Register> = (arguments.iter())
+                    .map(|argument| self.lower_value(builder, &argument.value))
+                    .collect();
+                let parameters = self.parameters.get(function).cloned();
+                for (variable, src) in parameters.unwrap_or_default().into_iter().zip(arguments) {
+                    builder.push(Instruction::Store { variable, src });
+                }
+
+                let zero = builder.register();
+                builder.push(Instruction::Const {
+                    dst: zero,
+                    value: 0,
+                });
+                builder.push(Instruction::Store {
+                    variable: *function,
+                    src: zero,
+                });
+                builder.push(Instruction::Call {
+                    function: *function,
+                });
+
+                let dst = builder.register();
+                builder.push(Instruction::Load {
+                    dst,
+                    variable: *function,
+                });
+                dst
+            }
+            BinOp((left, operator, right)) => {
+                let left = self.lower_value(builder, &left.value);
+                let right = self.lower_value(builder, &right.value);
+                let dst = builder.register();
+                builder.push(match operator {
+                    node::Operator::Add => Instruction::Add { dst, left, right },
+                    node::Operator::Subtract => Instruction::Subtract { dst, left, right },
+                });
+                dst
+            }
+            Assign((variable, value)) => {
+                let src = self.lower_value(builder, &value.value);
+                builder.push(Instruction::Store {
+                    variable: *variable,
+                    src,
+                });
+                src
+            }
+            IntLit(value) => {
+                let dst = builder.register();
+                builder.push(Instruction::Const { dst, value: *value });
+                dst
+            }
+            Input => {
+                let dst = builder.register();
+                builder.push(Instruction::Input { dst });
+                dst
+            }
+            // the parser only puts values here
+            _ => {
+                let dst = builder.register();
+                builder.push(Instruction::Const { dst, value: 0 });
+                dst
+            }
+        }
+    }
+
+    fn lower_scope(&mut self, builder: &mut Builder, scope: &node::Scope) {
+        use node::ScopeType::*;
+
+        match scope.kind {
+            Function => {
+                // anonymous functions can never be called
+                if let Some(name) = scope.name() {
+                    self.parameters.insert(name, scope.parameters());
+                    // the name in the signature is typed by what the body stores into it
+                    let signature = scope.signature.as_ref().map(|s| &s.expressions[0].value);
+                    if let Some(expression) = signature {
+                        self.returns.insert(name, self.kind(expression));
+                    }
+                    let function = self.lower_function(Some(name), &scope.body);
+                    self.functions.push(function);
+                }
+            }
+            Loop => {
+                let (header, body, exit) = (builder.block(), builder.block(), builder.block());
+                builder.terminate(Terminator::Jump(header), header);
+
+                let condition = self.lower_condition(builder, scope);
+                builder.terminate(
+                    Terminator::Branch {
+                        condition,
+                        then: body,
+                        otherwise: exit,
+                    },
+                    body,
+                );
+
+                self.lower_program(builder, &scope.body);
+                builder.terminate(Terminator::Jump(header), exit);
+            }
+            If => {
+                let (body, exit) = (builder.block(), builder.block());
+
+                let condition = self.lower_condition(builder, scope);
+                builder.terminate(
+                    Terminator::Branch {
+                        condition,
+                        then: body,
+                        otherwise: exit,
+                    },
+                    body,
+                );
+
+                self.lower_program(builder, &scope.body);
+                builder.terminate(Terminator::Jump(exit), exit);
+            }
+            Local => self.lower_program(builder, &scope.body),
+        }
+    }
+
+    fn lower_condition(&mut self, builder: &mut Builder, scope: &node::Scope) -> Register {
+        // a signature without a value is zero
+        let value = match &scope.signature {
+            Some(signature) => self.lower_statement(builder, signature),
+            None => None,
+        };
+
+        value.unwrap_or_else(|| {
+            let dst = builder.register();
+            builder.push(Instruction::Const { dst, value: 0 });
+            dst
+        })
+    }
+}
+
+// lowers the ast into a module, the program has to be resolved by parser::analyse first
+pub fn lower(program: &node::Program) -> Module {
+    let mut lowerer = Lowerer {
+        functions: Vec::new(),
+        parameters: HashMap::new(),
+        returns: HashMap::new(),
+    };
+
+    let entry = lowerer.lower_function(None, program);
+    let mut functions = vec![entry];
+    functions.append(&mut lowerer.functions);
+
+    Module { functions }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::build::{boxed, scope, spanned, statement, variable};
+    use node::Expression::*;
+
+    #[test]
+    fn lower_statement() {
+        let program = node::Program {
+            statements: vec![statement(vec![IntLit(3), variable(0), variable(1)])],
+        };
+
+        let test = lower(&program);
+        let expected = Module {
+            functions: vec![Function {
+                name: None,
+                parameters: Vec::new(),
+                blocks: vec![Block {
+                    instructions: vec![
+                        Instruction::Const { dst: 0, value: 3 },
+                        Instruction::Store {
+                            variable: 0,
+                            src: 0,
+                        },
+                        Instruction::Store {
+                            variable: 1,
+                            src: 0,
+                        },
+                    ],
+                    terminator: Terminator::Return,
+                }],
+                registers: 1,
+            }],
+        };
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn lower_scopes() {
+        let program = node::Program {
+            statements: vec![
+                statement(vec![scope(
+                    node::ScopeType::Loop,
+                    vec![variable(0)],
+                    vec![statement(vec![StringLit("a".to_string())])],
+                )]),
+                statement(vec![scope(
+                    node::ScopeType::Function,
+                    vec![variable(1)],
+                    vec![statement(vec![IntLit(0), variable(0)])],
+                )]),
+            ],
+        };
+
+        let test = lower(&program).to_string();
+        let expected = "\
+function main:
+b0:
+\tjump b1
+b1:
+\tr0 = load v0
+\tbranch r0, b2, b3
+b2:
+\tr1 = string \"a\"
+\tprint r1
+\tjump b1
+b3:
+\treturn
+
+function f1():
+b0:
+\tr0 = const 0
+\tstore v0, r0
+\treturn
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn lower_operators() {
+        let program = node::Program {
+            statements: vec![
+                statement(vec![Assign((
+                    0,
+                    boxed(BinOp((
+                        boxed(variable(0)),
+                        node::Operator::Add,
+                        boxed(IntLit(1)),
+                    ))),
+                ))]),
+                statement(vec![
+                    BinOp((
+                        boxed(variable(0)),
+                        node::Operator::Subtract,
+                        boxed(variable(1)),
+                    )),
+                    variable(1),
+                ]),
+            ],
+        };
+
+        let test = lower(&program).to_string();
+        let expected = "\
+function main:
+b0:
+\tr0 = load v0
+\tr1 = const 1
+\tr2 = add r0, r1
+\tstore v0, r2
+\tr3 = load v0
+\tr4 = load v1
+\tr5 = sub r3, r4
+\tstore v1, r5
+\treturn
+";
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn lower_call() {
+        let function = scope(
+            node::ScopeType::Function,
+            vec![variable(0), variable(1)],
+            vec![statement(vec![variable(1), variable(0)])],
+        );
+        let call = Call((0, vec![spanned(IntLit(5))]));
+        let program = node::Program {
+            statements: vec![
+                statement(vec![function]),
+                statement(vec![call, variable(2)]),
+            ],
+        };
+
+        let test = lower(&program).to_string();
+        let expected = "\
+function main:
+b0:
+\tr0 = const 5
+\tstore v1, r0
+\tr1 = const 0
+\tstore v0, r1
+\tcall f0
+\tr2 = load v0
+\tstore v2, r2
+\treturn
+
+function f0(v1):
+b0:
+\tr0 = load v1
+\tstore v0, r0
+\treturn
+";
+
+        assert_eq!(test, expected);
+    }
+}
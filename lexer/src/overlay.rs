@@ -0,0 +1,169 @@
+use crate::*;
+
+// everything the lexer found in a source image, drawn over a copy of it for debugging
+// draw order is lines, scopes, tokens then errors so errors are always visible
+
+const LINE: Rgb<u8> = Rgb([96, 96, 96]);
+const SCOPE: Rgb<u8> = Rgb([255, 255, 255]);
+const ERROR: Rgb<u8> = Rgb([255, 0, 0]);
+
+#[derive(Debug, Default)]
+pub struct Overlay {
+    pub tokens: Vec<Spanned<Lexeme>>,
+    pub lines: Vec<Tile>,  // tiles returned by analyse_line
+    pub scopes: Vec<Tile>, // rectangles found by detect_rectangle
+    pub errors: Vec<Tile>, // lexer and parser errors
+}
+
+// each kind of token gets its own colour
+fn colour(lexeme: &Lexeme) -> Rgb<u8> {
+    match lexeme {
+        Lexeme::Token(Token::Zero) => Rgb([0, 255, 255]),
+        Lexeme::Token(Token::Increment) => Rgb([0, 255, 0]),
+        Lexeme::Token(Token::Decrement) => Rgb([255, 128, 0]),
+        Lexeme::Token(Token::Access) => Rgb([0, 128, 255]),
+        Lexeme::Token(Token::Repeat) => Rgb([255, 0, 255]),
+        Lexeme::Token(Token::Quote) => Rgb([255, 255, 0]),
+        Lexeme::Token(Token::LineBreak) => Rgb([160, 160, 255]),
+        Lexeme::Token(Token::Input) => Rgb([255, 64, 64]),
+        Lexeme::Token(Token::ScopeStart | Token::ScopeEnd) => SCOPE,
+        Lexeme::Token(Token::Variable) | Lexeme::Identifier(_) => Rgb([255, 160, 200]),
+    }
+}
+
+// draws the edges of a tile, empty tiles are drawn as a single column or row
+fn outline(image: &mut image::RgbImage, tile: &Tile, colour: Rgb<u8>) {
+    let right = tile.x + (tile.width as usize).max(1) - 1;
+    let bottom = tile.y + (tile.height as usize).max(1) - 1;
+    let mut put = |x: usize, y: usize| {
+        if x < image.width() as usize && y < image.height() as usize {
+            image.put_pixel(x as u32, y as u32, colour);
+        }
+    };
+
+    for x in tile.x..=right {
+        put(x, tile.y);
+        put(x, bottom);
+    }
+    for y in tile.y..=bottom {
+        put(tile.x, y);
+        put(right, y);
+    }
+}
+
+impl Overlay {
+    fn render(&self, source: &image::DynamicImage) -> image::RgbImage {
+        let mut image = source.to_rgb8();
+
+        for line in &self.lines {
+            outline(&mut image, line, LINE);
+        }
+        for scope in &self.scopes {
+            outline(&mut image, scope, SCOPE);
+        }
+        for token in &self.tokens {
+            // scope tokens are already drawn as scopes
+            if !matches!(
+                token.value,
+                Lexeme::Token(Token::ScopeStart | Token::ScopeEnd)
+            ) {
+                outline(&mut image, &token.tile, colour(&token.value));
+            }
+        }
+        // errors are twice as thick so they stand out
+        for error in &self.errors {
+            outline(&mut image, error, ERROR);
+            outline(
+                &mut image,
+                &Tile {
+                    x: error.x.saturating_sub(1),
+                    y: error.y.saturating_sub(1),
+                    width: error.width + 2,
+                    height: error.height + 2,
+                },
+                ERROR,
+            );
+        }
+
+        image
+    }
+
+    pub fn save(&self, source: &String, out: &String) -> Result<(), Error> {
+        let source_img = ImageReader::open(source)?.with_guessed_format()?.decode()?;
+        self.render(&source_img).save(out)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_render() {
+        let source = image::DynamicImage::new_rgb8(16, 16);
+        let overlay = Overlay {
+            tokens: vec![Spanned {
+                value: Lexeme::Token(Token::Zero),
+                tile: Tile {
+                    x: 2,
+                    y: 2,
+                    width: 4,
+                    height: 4,
+                },
+            }],
+            lines: Vec::new(),
+            scopes: Vec::new(),
+            errors: vec![Tile {
+                x: 10,
+                y: 10,
+                width: 10,
+                height: 10,
+            }],
+        };
+        let image = overlay.render(&source);
+
+        let zero = colour(&Lexeme::Token(Token::Zero));
+        assert_eq!(*image.get_pixel(2, 2), zero);
+        assert_eq!(*image.get_pixel(5, 5), zero);
+        assert_eq!(*image.get_pixel(3, 3), Rgb([0, 0, 0])); // inside isnt filled
+        assert_eq!(*image.get_pixel(9, 12), ERROR); // thicker outline
+        assert_eq!(*image.get_pixel(15, 10), ERROR); // clipped to the image
+    }
+
+    #[test]
+    fn overlay_lexer() {
+        let key_img = ImageReader::open("../examples/key.png")
+            .unwrap()
+            .decode()
+            .unwrap();
+        let t = Lexeme::Token;
+        let lexemes = vec![
+            t(Token::Access),
+            Lexeme::Identifier(0),
+            t(Token::LineBreak),
+            t(Token::Repeat),
+            Lexeme::Identifier(0),
+            t(Token::ScopeStart),
+            t(Token::Quote),
+            t(Token::LineBreak),
+            t(Token::ScopeEnd),
+        ];
+        let src = image::DynamicImage::ImageRgb8(writer::render(&key_img, &lexemes, None).unwrap());
+
+        let mut lexer = Lexer::new(&src);
+        lexer.key.read_keys(&key_img, None).unwrap();
+        lexer.analyse().unwrap();
+
+        assert_eq!(lexer.scopes.len(), 1);
+        let scope = lexer.scopes[0];
+        // a line runs through the scope and another is inside it
+        assert!(lexer.lines.iter().any(|l| l.union(&scope) == *l));
+        assert!(lexer.lines.iter().any(|l| scope.union(l) == scope));
+        let start = lexer
+            .tokens
+            .iter()
+            .find(|s| s.value == t(Token::ScopeStart));
+        assert_eq!(start.map(|s| s.tile), Some(scope));
+    }
+}
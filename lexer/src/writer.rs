@@ -0,0 +1,389 @@
+use crate::*;
+
+// renders lexemes back into a source image by stamping the glyphs of the key file
+// the layout follows what the lexer expects:
+// - keys on a line share the row of their leftmost pixel
+// - every line ends with a line break
+// - scopes are rectangles wider and taller than a tile
+// - variables are solid squares with a unique colour and room for their declaration
+
+const MARGIN: u32 = 16; // space around lines and inside scopes
+const GAP: u32 = 12; // space between keys on a line
+const VARIABLE_SIZE: u32 = 10;
+
+// the pixels of a key cropped to its bounding box
+#[derive(Debug, Clone)]
+struct Glyph {
+    width: u32,
+    height: u32,
+    pixels: Vec<Option<Rgb<u8>>>,
+    leftmost: u32, // row of the top pixel in the leftmost column
+}
+
+impl Glyph {
+    // crops everything that isnt ignored out of a key tile
+    fn from_tile(tile: &[Vec<Rgb<u8>>], ignore: &[Rgb<u8>]) -> Option<Self> {
+        let solid = |p: &Rgb<u8>| !ignore.contains(p);
+
+        let rows: Vec<usize> = (0..tile.len())
+            .filter(|&y| tile[y].iter().any(solid))
+            .collect();
+        let columns: Vec<usize> = (0..tile.first()?.len())
+            .filter(|&x| tile.iter().any(|row| solid(&row[x])))
+            .collect();
+        let (top, bottom) = (*rows.first()?, *rows.last()?);
+        let (left, right) = (*columns.first()?, *columns.last()?);
+
+        let mut glyph = Self {
+            width: (right - left + 1) as u32,
+            height: (bottom - top + 1) as u32,
+            pixels: Vec::new(),
+            leftmost: 0,
+        };
+        for row in &tile[top..=bottom] {
+            for pixel in &row[left..=right] {
+                glyph.pixels.push(Some(*pixel).filter(solid));
+            }
+        }
+        glyph.leftmost = (0..glyph.height)
+            .find(|&y| glyph.pixel(0, y).is_some())
+            .unwrap_or(0);
+
+        Some(glyph)
+    }
+
+    fn square(colour: Rgb<u8>, size: u32) -> Self {
+        Self {
+            width: size,
+            height: size,
+            pixels: vec![Some(colour); (size * size) as usize],
+            leftmost: 0,
+        }
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Option<Rgb<u8>> {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+// a transparent image that glyphs and other canvases are drawn onto
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<Option<Rgb<u8>>>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![None; (width * height) as usize],
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, colour: Rgb<u8>) {
+        if x < self.width && y < self.height {
+            self.pixels[(y * self.width + x) as usize] = Some(colour);
+        }
+    }
+
+    fn fill(&mut self, colour: Rgb<u8>) {
+        self.pixels.iter_mut().for_each(|p| *p = Some(colour));
+    }
+
+    fn stamp(&mut self, glyph: &Glyph, x: u32, y: u32) {
+        for gy in 0..glyph.height {
+            for gx in 0..glyph.width {
+                if let Some(colour) = glyph.pixel(gx, gy) {
+                    self.put(x + gx, y + gy, colour);
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, canvas: &Canvas, x: u32, y: u32) {
+        for cy in 0..canvas.height {
+            for cx in 0..canvas.width {
+                if let Some(colour) = canvas.pixels[(cy * canvas.width + cx) as usize] {
+                    self.put(x + cx, y + cy, colour);
+                }
+            }
+        }
+    }
+
+    fn to_image(&self, background: Rgb<u8>) -> image::RgbImage {
+        image::RgbImage::from_fn(self.width, self.height, |x, y| {
+            self.pixels[(y * self.width + x) as usize].unwrap_or(background)
+        })
+    }
+}
+
+// lines and scopes of the lexemes
+enum Element {
+    Line(Vec<Lexeme>),
+    Scope(Vec<Element>),
+}
+
+// groups lexemes into lines and scopes
+fn elements(lexemes: &mut std::iter::Peekable<std::slice::Iter<Lexeme>>) -> Vec<Element> {
+    let mut elements = Vec::new();
+    let mut line = Vec::new();
+
+    while let Some(lexeme) = lexemes.next() {
+        match lexeme {
+            Lexeme::Token(Token::ScopeStart) => {
+                if !line.is_empty() {
+                    elements.push(Element::Line(std::mem::take(&mut line)));
+                }
+                elements.push(Element::Scope(self::elements(lexemes)));
+            }
+            Lexeme::Token(Token::ScopeEnd) => break,
+            Lexeme::Token(Token::LineBreak) => {
+                elements.push(Element::Line(std::mem::take(&mut line)));
+            }
+            lexeme => line.push(lexeme.clone()),
+        }
+    }
+    if !line.is_empty() {
+        elements.push(Element::Line(line));
+    }
+
+    elements
+}
+
+struct Writer {
+    glyphs: Vec<Glyph>, // glyphs of the key file in key order
+    variables: Vec<Glyph>,
+    reserved: Vec<Rgb<u8>>, // colours that cant be used for new glyphs or scopes
+    frame: (u32, u32),      // size of the frames the lexer searches for lines in
+    tile_size: u32,         // size of the key files tiles
+}
+
+impl Writer {
+    fn new(
+        key: &mut Key,
+        image: &image::DynamicImage,
+        tile_size: Option<usize>,
+    ) -> Result<Self, Error> {
+        key.read_keys(image, tile_size)?;
+
+        let ignore = [key.background, key.grid];
+        let glyphs = key
+            .image_to_tiles(image)
+            .iter()
+            .take(key.data().len())
+            .map(|tile| Glyph::from_tile(tile, &ignore).unwrap_or(Glyph::square(key.grid, 1)))
+            .collect();
+
+        let mut reserved: Vec<Rgb<u8>> = (key.data().iter())
+            .flat_map(|k| k.colours.iter().map(|&(colour, _)| colour))
+            .collect();
+        reserved.extend(ignore);
+
+        let largest = key.get_largest();
+        Ok(Self {
+            glyphs,
+            variables: Vec::new(),
+            reserved,
+            frame: largest,
+            tile_size: key.tile_size as u32,
+        })
+    }
+
+    // a colour that hasnt been used yet
+    fn colour(&mut self, seed: usize) -> Rgb<u8> {
+        let mut seed = seed as u32;
+        loop {
+            // spreads the hue around so colours stay distinguishable
+            let hue = (seed * 137) % 360;
+            let value = 255 - (seed / 360 * 16 % 128) as u8;
+            let colour = hsv(hue, value);
+            if !self.reserved.contains(&colour) {
+                self.reserved.push(colour);
+                return colour;
+            }
+            seed += 1;
+        }
+    }
+
+    fn glyph(&mut self, lexeme: &Lexeme) -> Glyph {
+        match lexeme {
+            Lexeme::Token(token) => self.glyphs[*token as usize].clone(),
+            Lexeme::Identifier(id) => {
+                while self.variables.len() <= *id {
+                    let colour = self.colour(self.variables.len());
+                    self.variables.push(Glyph::square(colour, VARIABLE_SIZE));
+                }
+                self.variables[*id].clone()
+            }
+        }
+    }
+
+    fn line(&mut self, lexemes: &[Lexeme]) -> Canvas {
+        let mut glyphs: Vec<(Glyph, u32)> = Vec::new(); // glyph with the space after it
+        for (i, lexeme) in lexemes.iter().enumerate() {
+            // the lexer reads a tiles width of pixels as the variables symbol
+            let declaration = i > 0 && lexemes[i - 1] == Lexeme::Token(Token::Access);
+            let space = match declaration {
+                true => self.tile_size,
+                false => GAP,
+            };
+            glyphs.push((self.glyph(lexeme), space));
+        }
+        glyphs.push((self.glyph(&Lexeme::Token(Token::LineBreak)), 0));
+
+        let above = glyphs.iter().map(|(g, _)| g.leftmost).max().unwrap_or(0);
+        let below = glyphs
+            .iter()
+            .map(|(g, _)| g.height - g.leftmost)
+            .max()
+            .unwrap_or(0);
+        let width = glyphs.iter().map(|(g, space)| g.width + space).sum();
+
+        let mut canvas = Canvas::new(width, above + below);
+        let mut x = 0;
+        for (glyph, space) in &glyphs {
+            canvas.stamp(glyph, x, above - glyph.leftmost);
+            x += glyph.width + space;
+        }
+
+        canvas
+    }
+
+    fn scope(&mut self, elements: &[Element], depth: usize) -> Canvas {
+        let body = self.block(elements, depth + 1);
+
+        // the lexer searches scopes in frames so the width has to fit them exactly
+        let width = (body.width + MARGIN * 2).max(self.tile_size + 1);
+        let width = width.div_ceil(self.frame.0) * self.frame.0;
+        let height = (body.height + MARGIN * 2).max(self.tile_size + 1);
+
+        let mut canvas = Canvas::new(width, height);
+        canvas.fill(self.colour(depth * 7 + 3));
+        canvas.draw(&body, MARGIN, MARGIN);
+
+        canvas
+    }
+
+    fn block(&mut self, elements: &[Element], depth: usize) -> Canvas {
+        let canvases: Vec<Canvas> = elements
+            .iter()
+            .map(|element| match element {
+                Element::Line(lexemes) => self.line(lexemes),
+                Element::Scope(elements) => self.scope(elements, depth),
+            })
+            .collect();
+
+        // leaving a frame between lines so the lexer doesnt confuse them
+        let gap = self.frame.1;
+        let width = canvases.iter().map(|c| c.width).max().unwrap_or(0);
+        let height = canvases.iter().map(|c| c.height + gap).sum::<u32>();
+
+        let mut block = Canvas::new(width, height.saturating_sub(gap));
+        let mut y = 0;
+        for canvas in &canvases {
+            block.draw(canvas, 0, y);
+            y += canvas.height + gap;
+        }
+
+        block
+    }
+}
+
+// fully saturated colour from a hue in degrees
+fn hsv(hue: u32, value: u8) -> Rgb<u8> {
+    let x = (value as u32 * (60 - (hue % 120).abs_diff(60)) / 60) as u8;
+    match hue / 60 {
+        0 => Rgb([value, x, 0]),
+        1 => Rgb([x, value, 0]),
+        2 => Rgb([0, value, x]),
+        3 => Rgb([0, x, value]),
+        4 => Rgb([x, 0, value]),
+        _ => Rgb([value, 0, x]),
+    }
+}
+
+// draws the lexemes into a source image using the glyphs of the key file
+pub(crate) fn render(
+    key_img: &image::DynamicImage,
+    lexemes: &[Lexeme],
+    tile_size: Option<usize>,
+) -> Result<image::RgbImage, Error> {
+    let mut key = Key::new();
+    let mut writer = Writer::new(&mut key, key_img, tile_size)?;
+
+    let block = writer.block(&elements(&mut lexemes.iter().peekable()), 0);
+    let mut canvas = Canvas::new(block.width + MARGIN * 2, block.height + MARGIN * 2);
+    canvas.draw(&block, MARGIN, MARGIN);
+
+    Ok(canvas.to_image(key.background))
+}
+
+pub fn serialize(
+    key: &String,
+    lexemes: &[Lexeme],
+    out: &String,
+    options: &Options,
+) -> Result<(), Error> {
+    let key_img = read_key_image(key, options)?;
+    render(&key_img, lexemes, options.tile_size)?.save(out)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Lexeme::Identifier as Id;
+    use Token::*;
+
+    // renders the lexemes and tokenizes them again
+    fn round_trip(lexemes: &[Lexeme]) -> Vec<Lexeme> {
+        let key_img = ImageReader::open("../examples/key.png")
+            .unwrap()
+            .decode()
+            .unwrap();
+        let src = image::DynamicImage::ImageRgb8(render(&key_img, lexemes, None).unwrap());
+
+        let mut lexer = Lexer::new(&src);
+        lexer.key.read_keys(&key_img, None).unwrap();
+        lexer.analyse().unwrap();
+
+        lexer.tokens.into_iter().map(|t| t.value).collect()
+    }
+
+    #[test]
+    fn writer_round_trip() {
+        let t = Lexeme::Token;
+        let lexemes = vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(Zero),
+            t(Increment),
+            t(Increment),
+            Id(0),
+            t(LineBreak),
+            t(ScopeStart),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            t(Quote),
+            t(Increment),
+            t(Quote),
+            t(LineBreak),
+            t(ScopeEnd),
+            t(ScopeStart),
+            t(Repeat),
+            Id(0),
+            t(LineBreak),
+            t(Input),
+            Id(0),
+            t(LineBreak),
+            t(ScopeEnd),
+        ];
+
+        assert_eq!(round_trip(&lexemes), lexemes);
+    }
+}
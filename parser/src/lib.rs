@@ -1,9 +1,14 @@
 use lexer::*;
+pub use lexer::{Spanned, Tile};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+mod semantic;
+pub use semantic::analyse;
 
 pub mod node {
     use crate::SymbolType;
+    use lexer::{Spanned, Tile};
 
     #[derive(Default, Debug)]
     pub struct Program {
@@ -12,7 +17,18 @@ pub mod node {
 
     #[derive(Default, Debug)]
     pub struct Statement {
-        pub expressions: Vec<Expression>,
+        pub expressions: Vec<Spanned<Expression>>,
+        pub tile: Tile, // covers every expression
+    }
+
+    impl Statement {
+        pub fn push(&mut self, expression: Spanned<Expression>) {
+            self.tile = match self.expressions.is_empty() {
+                true => expression.tile,
+                false => self.tile.union(&expression.tile),
+            };
+            self.expressions.push(expression);
+        }
     }
 
     #[derive(Debug)]
@@ -21,8 +37,35 @@ pub mod node {
         ScopeEnd, // TODO: better way to find scopeEnd this is not good
         IntLit(isize),
         StringLit(String),
-        Variable((usize, SymbolType)), // (id, type)
-                                       // TODO: variables should also take into account scope therefore i do not think that we should store variable data like this.
+        Input,                                   // the next byte of stdin
+        Variable((usize, SymbolType)),           // (id, type)
+        Declaration((usize, SymbolType)), // an access before the variable, otherwise like one
+        Call((usize, Vec<Spanned<Expression>>)), // (function id, arguments)
+        BinOp((Box<Spanned<Expression>>, Operator, Box<Spanned<Expression>>)), // (left, operator, right)
+        Assign((usize, Box<Spanned<Expression>>)), // (variable id, value)
+    }
+
+    impl Expression {
+        // whether it sets the value of its statement
+        pub fn is_value(&self) -> bool {
+            matches!(
+                self,
+                Expression::IntLit(_)
+                    | Expression::StringLit(_)
+                    | Expression::Input
+                    | Expression::Variable(_)
+                    | Expression::Declaration(_)
+                    | Expression::Call(_)
+                    | Expression::BinOp(_)
+                    | Expression::Assign(_)
+            )
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Operator {
+        Add,
+        Subtract,
     }
 
     // scopes
@@ -41,43 +84,260 @@ pub mod node {
         pub signature: Option<Statement>,
         pub body: Program,
     }
+
+    impl Scope {
+        // the variable a function is named by, the first in its signature
+        pub fn name(&self) -> Option<usize> {
+            match self.signature.as_ref()?.expressions.first()?.value {
+                Expression::Variable((id, _)) | Expression::Declaration((id, _)) => Some(id),
+                _ => None,
+            }
+        }
+
+        // the variables a functions arguments are stored in, the rest of its signature
+        pub fn parameters(&self) -> Vec<usize> {
+            let Some(signature) = &self.signature else {
+                return Vec::new();
+            };
+            (signature.expressions.iter().skip(1))
+                .filter_map(|expression| match expression.value {
+                    Expression::Variable((id, _)) | Expression::Declaration((id, _)) => Some(id),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    // builds programs by hand for tests, every tile is left empty
+    #[cfg(any(test, feature = "test-util"))]
+    pub mod build {
+        use super::*;
+
+        pub fn spanned<T>(value: T) -> Spanned<T> {
+            Spanned {
+                value,
+                tile: Tile::default(),
+            }
+        }
+
+        // an operand of a binary operator or an assignment
+        pub fn boxed(value: Expression) -> Box<Spanned<Expression>> {
+            Box::new(spanned(value))
+        }
+
+        pub fn statement(expressions: Vec<Expression>) -> Statement {
+            let mut statement = Statement::default();
+            for value in expressions {
+                statement.push(spanned(value));
+            }
+            statement
+        }
+
+        pub fn variable(id: usize) -> Expression {
+            Expression::Variable((id, SymbolType::Undefined))
+        }
+
+        pub fn scope(
+            kind: ScopeType,
+            signature: Vec<Expression>,
+            body: Vec<Statement>,
+        ) -> Expression {
+            Expression::Scope(Scope {
+                kind,
+                signature: Some(statement(signature)),
+                body: Program { statements: body },
+            })
+        }
+
+        // the analysed program in an image of the test directory, read with the example key
+        pub fn source_image(source: &str) -> Program {
+            let root = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+            let mut tokens = lexer::deserialize(
+                &format!("{root}/examples/key.png"),
+                &format!("{root}/test/{source}"),
+                &Default::default(),
+            )
+            .unwrap();
+            let mut program = crate::parse(&mut tokens).unwrap();
+            assert!(crate::analyse(&mut program).is_empty());
+            program
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SymbolType {
     Undefined,
-    Int(usize),
-    String(String),
+    Int,
+    String,
+    Function(usize), // amount of parameters
+}
+
+impl std::fmt::Display for SymbolType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SymbolType::Undefined => write!(f, "nothing"),
+            SymbolType::Int => write!(f, "an integer"),
+            SymbolType::String => write!(f, "a string"),
+            SymbolType::Function(_) => write!(f, "a function"),
+        }
+    }
 }
 
-// each symbol (variable, function)
-struct Symbol {
-    // name: String,
-    kind: SymbolType,
+// syntax errors stop the parser, the rest are found by semantic::analyse
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // token cant be used where it is
+    UnexpectedToken {
+        token: Token,
+        tile: Tile,
+    },
+    UnmatchedScopeEnd {
+        tile: Tile,
+    },
+    // source ended inside the scope
+    UnclosedScope {
+        tile: Tile,
+    },
+    // scope signature isnt a function, a loop or an if
+    InvalidScope {
+        tile: Tile,
+    },
+    // access without a variable after it
+    ExpectedIdentifier {
+        tile: Tile,
+    },
+    // quoted integer isnt a character
+    InvalidCharacter {
+        tile: Tile,
+    },
+    // function parameter isnt a variable
+    InvalidParameter {
+        tile: Tile,
+    },
+    UndeclaredVariable {
+        id: usize,
+        tile: Tile,
+    },
+    // used after the scope declaring it ended
+    OutOfScope {
+        id: usize,
+        tile: Tile,
+        declared: Tile,
+    },
+    // call has fewer values after it than parameters, values after the last parameter are left
+    // for the rest of the statement
+    ExpectedArgument {
+        tile: Tile,
+    },
+    // condition or operand isnt an integer
+    ExpectedInt {
+        tile: Tile,
+    },
+    // variable is given a value of another type than it holds
+    TypeMismatch {
+        id: usize,
+        tile: Tile,
+        expected: SymbolType,
+        found: SymbolType,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedToken { token, tile } => write!(f, "unexpected {token:?} at {tile}"),
+            Error::UnmatchedScopeEnd { tile } => write!(f, "unmatched scope end at {tile}"),
+            Error::UnclosedScope { tile } => write!(f, "unclosed scope at {tile}"),
+            Error::InvalidScope { tile } => {
+                write!(
+                    f,
+                    "scope at {tile} doesnt start with an access, a repeat or a value"
+                )
+            }
+            Error::ExpectedIdentifier { tile } => {
+                write!(f, "expected a variable after the access at {tile}")
+            }
+            Error::InvalidCharacter { tile } => write!(f, "{tile} isnt a character"),
+            Error::UndeclaredVariable { id, tile } => {
+                write!(f, "variable {id} used before its declared at {tile}")
+            }
+            Error::OutOfScope { id, tile, declared } => write!(
+                f,
+                "variable {id} used at {tile} outside the scope it was declared in at {declared}"
+            ),
+            Error::InvalidParameter { tile } => {
+                write!(f, "function parameter at {tile} isnt a variable")
+            }
+            Error::ExpectedArgument { tile } => {
+                write!(f, "expected another argument for the call at {tile}")
+            }
+            Error::ExpectedInt { tile } => write!(f, "expected an integer at {tile}"),
+            Error::TypeMismatch {
+                id,
+                tile,
+                expected,
+                found,
+            } => write!(
+                f,
+                "variable {id} at {tile} holds {expected} but is given {found}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn tile(&self) -> Tile {
+        match self {
+            Error::UnexpectedToken { tile, .. }
+            | Error::UnmatchedScopeEnd { tile }
+            | Error::UnclosedScope { tile }
+            | Error::InvalidScope { tile }
+            | Error::ExpectedIdentifier { tile }
+            | Error::InvalidCharacter { tile }
+            | Error::UndeclaredVariable { tile, .. }
+            | Error::OutOfScope { tile, .. }
+            | Error::InvalidParameter { tile }
+            | Error::ExpectedArgument { tile }
+            | Error::ExpectedInt { tile }
+            | Error::TypeMismatch { tile, .. } => *tile,
+        }
+    }
 }
 
 struct Parser<'a> {
-    tokens: &'a mut Vec<Lexeme>,
-    symbol_table: HashMap<usize, SymbolType>,
+    tokens: &'a mut Vec<Spanned<Lexeme>>,
+    // identifiers declared in each open scope, innermost last, with the parameters of functions
+    // calls cant be parsed without knowing how many values they take, the rest is left to semantic
+    names: Vec<HashMap<usize, Option<usize>>>,
+    tile: Tile, // tile of the last lexeme taken
 }
 
 impl Parser<'_> {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.tokens.last().map(|lexeme| &lexeme.value)
+    }
+
+    fn pop(&mut self) -> Option<Lexeme> {
+        let lexeme = self.tokens.pop()?;
+        self.tile = lexeme.tile;
+        Some(lexeme.value)
+    }
+
     // used to evaluate integer literal expressions involving increment and decrement
     fn eval_lit(&mut self) -> isize {
         let mut value = Default::default();
 
-        while let Some(lexeme) = self.tokens.pop() {
-            if lexeme == Lexeme::Token(Token::LineBreak) {
-                self.tokens.push(Lexeme::Token(Token::LineBreak));
-                break;
-            }
-
-            let increment = lexeme == Lexeme::Token(Token::Increment);
-            let decrement = lexeme == Lexeme::Token(Token::Decrement);
+        while let Some(lexeme) = self.peek() {
+            let increment = *lexeme == Lexeme::Token(Token::Increment);
+            let decrement = *lexeme == Lexeme::Token(Token::Decrement);
 
             if !increment && !decrement {
                 break;
             }
+            self.pop();
 
             value += increment as isize;
             value -= decrement as isize;
@@ -86,120 +346,596 @@ impl Parser<'_> {
         value
     }
 
-    fn parse_int(&mut self) -> Option<isize> {
-        Some(self.eval_lit())
+    fn parse_int(&mut self) -> isize {
+        self.eval_lit()
     }
 
-    // TODO: return type Result with ScopeError type that can be error for condition, body, etc.
-    fn parse_scope(&mut self) -> Option<node::Scope> {
-        let mut scope: node::Scope = Default::default();
-
-        scope.kind = match self.tokens.pop() {
+    fn parse_scope(&mut self) -> Result<node::Scope, Error> {
+        let start = self.tile;
+        let kind = match self.peek() {
+            // the functions name is declared like any other variable
             Some(Lexeme::Token(Token::Access)) => node::ScopeType::Function,
-            Some(Lexeme::Token(Token::Repeat)) => node::ScopeType::Loop,
-            // TODO: if statement
+            Some(Lexeme::Token(Token::Repeat)) => {
+                self.pop();
+                node::ScopeType::Loop
+            }
+            // the value is the condition
+            Some(Lexeme::Token(Token::Zero) | Lexeme::Identifier(_)) => node::ScopeType::If,
+            _ => return Err(Error::InvalidScope { tile: start }),
+        };
+
+        // the signature is declared inside the scope so parameters are local to it
+        self.names.push(HashMap::new());
+        let signature = self.parse_line()?;
+        if let node::ScopeType::Function = kind {
+            if let Some(parameter) = (signature.expressions.iter()).find(|e| {
+                !matches!(
+                    e.value,
+                    node::Expression::Variable(_) | node::Expression::Declaration(_)
+                )
+            }) {
+                return Err(Error::InvalidParameter {
+                    tile: parameter.tile,
+                });
+            }
+        }
+        let scope = node::Scope {
+            kind,
+            signature: Some(signature),
+            body: self.parse_body(start)?,
+        };
+
+        self.names.pop();
+
+        // inside the body the name is the return value, after it the name calls the function
+        if let (node::ScopeType::Function, Some(name)) = (&scope.kind, scope.name()) {
+            let parameters = scope.parameters().len();
+            self.declare(name, Some(parameters));
+        }
+
+        Ok(scope)
+    }
+
+    // a call takes a value for each parameter from the rest of the line
+    // missing values are left for semantic to report
+    fn parse_call(&mut self, function: usize, parameters: usize) -> node::Expression {
+        let mut arguments = Vec::new();
+        while arguments.len() < parameters {
+            match self.parse_operand() {
+                Some(argument) => arguments.push(argument),
+                None => break,
+            }
+        }
+
+        node::Expression::Call((function, arguments))
+    }
+
+    // the values taken by calls and operators, integer literals and variables that arent functions
+    fn parse_operand(&mut self) -> Option<Spanned<node::Expression>> {
+        let value = match self.peek()? {
+            Lexeme::Token(Token::Zero) => {
+                self.pop();
+                let start = self.tile;
+                let value = node::Expression::IntLit(self.parse_int());
+                return Some(Spanned {
+                    value,
+                    tile: start.union(&self.tile),
+                });
+            }
+            &Lexeme::Identifier(id) if self.function(id).is_none() => {
+                self.pop();
+                node::Expression::Variable((id, SymbolType::Undefined))
+            }
             _ => return None,
         };
 
-        scope.signature = Some(self.parse_line()?);
+        Some(Spanned {
+            value,
+            tile: self.tile,
+        })
+    }
+
+    // an increment or decrement after a value
+    // followed by another value it adds or subtracts the two, otherwise it changes the value by one
+    // for each increment and decrement, in place if the value is a variable
+    fn parse_operator(
+        &mut self,
+        statement: &mut node::Statement,
+        token: Token,
+    ) -> Result<(), Error> {
+        use node::Expression::*;
+
+        let tile = self.tile;
+        let Some(last) = statement.expressions.last().filter(|e| e.value.is_value()) else {
+            return Err(Error::UnexpectedToken { token, tile });
+        };
+
+        // a variable storing the value stays a store and its new value is used instead
+        let stored = (statement.expressions.iter().rev().skip(1)).any(|e| e.value.is_value());
+        let left = match (&last.value, stored) {
+            (Variable((id, kind)) | Declaration((id, kind)), true) => Spanned {
+                value: Variable((*id, *kind)),
+                tile: last.tile,
+            },
+            // `unwrap`: the last expression exists
+            _ => statement.expressions.pop().unwrap(),
+        };
+        let start = left.tile;
 
-        scope.body = self.parse_body()?;
+        let operator = match token {
+            Token::Decrement => node::Operator::Subtract,
+            _ => node::Operator::Add,
+        };
+        let value = match self.parse_operand() {
+            Some(right) => BinOp((Box::new(left), operator, Box::new(right))),
+            None => {
+                let step = match operator {
+                    node::Operator::Add => 1,
+                    node::Operator::Subtract => -1,
+                };
+                let amount = Spanned {
+                    value: IntLit(step + self.eval_lit()),
+                    tile: tile.union(&self.tile),
+                };
+                let variable = match &left.value {
+                    Variable((id, _)) | Declaration((id, _)) => Some(*id),
+                    _ => None,
+                };
+                let value = BinOp((Box::new(left), node::Operator::Add, Box::new(amount)));
+                match variable {
+                    Some(id) => Assign((
+                        id,
+                        Box::new(Spanned {
+                            value,
+                            tile: start.union(&self.tile),
+                        }),
+                    )),
+                    None => value,
+                }
+            }
+        };
 
-        Some(scope)
+        statement.push(Spanned {
+            value,
+            tile: start.union(&self.tile),
+        });
+        Ok(())
     }
 
-    fn parse_quote(&mut self) -> Option<node::Expression> {
-        let string = node::Expression::StringLit(self.parse_int()?.to_string());
-        self.tokens.pop(); // pops the ending quote
-        Some(string)
+    // each integer between the quotes is a character, the first starts at the quote and the rest
+    // at a zero. `Quote Quote` is the empty string
+    fn parse_quote(&mut self) -> Result<node::Expression, Error> {
+        let mut string = String::new();
+        let mut start = self.tile;
+        loop {
+            let int = self.parse_int();
+            let tile = start.union(&self.tile);
+            if string.is_empty() && int == 0 && self.peek() == Some(&Lexeme::Token(Token::Quote)) {
+                break;
+            }
+            match u32::try_from(int)
+                .ok()
+                .filter(|&int| int != 0)
+                .and_then(char::from_u32)
+            {
+                Some(c) => string.push(c),
+                None => return Err(Error::InvalidCharacter { tile }),
+            }
+
+            if self.peek() != Some(&Lexeme::Token(Token::Zero)) {
+                break;
+            }
+            self.pop();
+            start = self.tile;
+        }
+
+        // pops the ending quote
+        if self.peek() == Some(&Lexeme::Token(Token::Quote)) {
+            self.pop();
+        }
+        Ok(node::Expression::StringLit(string))
     }
 
     // TODO: this is duplicate code for parse()
-    fn parse_body(&mut self) -> Option<node::Program> {
+    fn parse_body(&mut self, scope: Tile) -> Result<node::Program, Error> {
         let mut program: node::Program = Default::default();
 
-        let mut line = self.parse_line();
-        while !line.as_ref()?.expressions.is_empty() {
-            program.statements.push(line.unwrap());
-
-            line = self.parse_line();
-            match self.tokens.last()? {
+        loop {
+            match self.peek().ok_or(Error::UnclosedScope { tile: scope })? {
                 Lexeme::Token(Token::ScopeEnd) => {
-                    self.tokens.pop();
+                    self.pop();
                     break;
                 }
-                _ => continue,
+                _ => {
+                    let line = self.parse_line()?;
+                    if !line.expressions.is_empty() {
+                        program.statements.push(line);
+                    }
+                }
             }
         }
 
-        Some(program)
+        Ok(program)
     }
 
-    // adds a variable to the symbol_table
-    fn add_var(&mut self) -> Option<node::Expression> {
-        let id = self.tokens.pop();
-        // TODO: do we need to check this?
-        if let Some(Lexeme::Identifier(id)) = id {
-            self.symbol_table.insert(id, SymbolType::Undefined);
-            return Some(node::Expression::Variable((id, SymbolType::Undefined)));
-        }
+    fn declare(&mut self, id: usize, parameters: Option<usize>) {
+        // `unwrap`: the outermost scope is never popped
+        self.names.last_mut().unwrap().insert(id, parameters);
+    }
 
-        None
+    // the parameters of the function an identifier names, None for variables
+    fn function(&self, id: usize) -> Option<usize> {
+        (self.names.iter().rev())
+            .find_map(|names| names.get(&id))
+            .copied()
+            .flatten()
     }
 
-    fn replace_var(&self, id: usize) -> Option<&SymbolType> {
-        self.symbol_table.get(&id)
+    fn add_var(&mut self) -> Result<node::Expression, Error> {
+        match self.pop() {
+            Some(Lexeme::Identifier(id)) => {
+                self.declare(id, None);
+                Ok(node::Expression::Declaration((id, SymbolType::Undefined)))
+            }
+            _ => Err(Error::ExpectedIdentifier { tile: self.tile }),
+        }
     }
 
-    fn parse_line(&mut self) -> Option<node::Statement> {
+    fn parse_line(&mut self) -> Result<node::Statement, Error> {
         use node::Expression::*;
 
         let mut statement: node::Statement = Default::default();
 
         // TODO: should node::Expressions be put here or should the parsing functions return them?
-        // TODO: replace unwraps with proper error handling
-        while let Some(lexeme) = self.tokens.pop() {
-            println!("{lexeme:?}");
-            statement.expressions.push(match lexeme {
-                Lexeme::Token(Token::Zero) => IntLit(self.parse_int()?),
-                Lexeme::Token(Token::Increment) => unreachable!(),
-                Lexeme::Token(Token::Decrement) => unreachable!(),
+        while let Some(lexeme) = self.peek().cloned() {
+            match lexeme {
+                // left for the next statement
+                Lexeme::Token(Token::ScopeStart) if !statement.expressions.is_empty() => break,
+                // left for parse_body() to close the scope
+                Lexeme::Token(Token::ScopeEnd) => break,
+                _ => self.pop(),
+            };
+            let start = self.tile;
+
+            let expression = match lexeme {
+                Lexeme::Token(Token::Zero) => IntLit(self.parse_int()),
                 Lexeme::Token(Token::Access) => self.add_var()?,
-                Lexeme::Token(Token::Variable) => unreachable!(),
-                Lexeme::Identifier(id) => Variable((id, self.replace_var(id)?.clone())), // TODO: maybe part of parse_int()
-                Lexeme::Token(Token::Repeat) => unreachable!(),
+                Lexeme::Identifier(id) => match self.function(id) {
+                    Some(parameters) => self.parse_call(id, parameters),
+                    None => Variable((id, SymbolType::Undefined)), // TODO: maybe part of parse_int()
+                },
                 Lexeme::Token(Token::Quote) => self.parse_quote()?,
-                Lexeme::Token(Token::ScopeStart) => Scope(self.parse_scope()?),
-                Lexeme::Token(Token::ScopeEnd) => return Some(statement),
+                Lexeme::Token(Token::Input) => Input,
+                // a scope is a statement of its own
+                Lexeme::Token(Token::ScopeStart) => {
+                    statement.push(Spanned {
+                        value: Scope(self.parse_scope()?),
+                        tile: start,
+                    });
+                    break;
+                }
+                Lexeme::Token(Token::LineBreak) => break,
+                // operators replace the value before them
+                Lexeme::Token(token @ (Token::Increment | Token::Decrement)) => {
+                    self.parse_operator(&mut statement, token)?;
+                    continue;
+                }
+                // repeats only start scopes
+                Lexeme::Token(token) => return Err(Error::UnexpectedToken { token, tile: start }),
+            };
 
-                Lexeme::Token(Token::LineBreak) => return Some(statement),
+            statement.push(Spanned {
+                value: expression,
+                tile: start.union(&self.tile),
             });
         }
 
-        None
+        Ok(statement)
     }
 }
 
-pub fn parse(tokens: &mut Vec<Lexeme>) -> Result<node::Program, String> {
+// builds the tree, stopping at the first syntax error
+// identifiers are left as they are in the source, analyse resolves them to their declarations and
+// has to run before the program is lowered
+pub fn parse(tokens: &mut Vec<Spanned<Lexeme>>) -> Result<node::Program, Error> {
     tokens.reverse(); // TODO: is reversing first faster than pop_back()?
     let mut parser = Parser {
         tokens,
-        symbol_table: HashMap::new(),
+        names: vec![HashMap::new()],
+        tile: Default::default(),
     };
     let mut program: node::Program = Default::default();
 
-    let (mut line, mut count) = (parser.parse_line(), 2);
-    while !line
-        .as_ref()
-        .ok_or(format!("invalid syntax at line {}", count))?
-        .expressions
-        .is_empty()
-    {
-        program.statements.push(line.unwrap());
+    while let Some(lexeme) = parser.tokens.last() {
+        if lexeme.value == Lexeme::Token(Token::ScopeEnd) {
+            return Err(Error::UnmatchedScopeEnd { tile: lexeme.tile });
+        }
 
-        line = parser.parse_line();
-        count += 1
+        let line = parser.parse_line()?;
+        if !line.expressions.is_empty() {
+            program.statements.push(line);
+        }
     }
 
     Ok(program)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Lexeme::Identifier as Id;
+    use Token::*;
+
+    pub(crate) fn t(token: Token) -> Lexeme {
+        Lexeme::Token(token)
+    }
+
+    // lays the lexemes out in a row of 10x10 tiles
+    pub(crate) fn spanned(lexemes: Vec<Lexeme>) -> Vec<Spanned<Lexeme>> {
+        lexemes
+            .into_iter()
+            .enumerate()
+            .map(|(i, lexeme)| Spanned {
+                value: lexeme,
+                tile: tile(i, 1),
+            })
+            .collect()
+    }
+
+    pub(crate) fn tile(i: usize, length: u32) -> Tile {
+        Tile {
+            x: i * 10,
+            y: 0,
+            width: length * 10,
+            height: 10,
+        }
+    }
+
+    #[test]
+    fn parser_spans() {
+        let mut tokens = spanned(vec![
+            t(Zero),
+            t(Increment),
+            t(Increment),
+            t(Access),
+            Id(0),
+            t(Quote),
+            t(Increment),
+            t(Quote),
+            t(LineBreak),
+        ]);
+        let program = parse(&mut tokens).unwrap();
+
+        let statement = &program.statements[0];
+        let test: Vec<Tile> = statement.expressions.iter().map(|e| e.tile).collect();
+        let expected = vec![tile(0, 3), tile(3, 2), tile(5, 3)];
+
+        assert_eq!(test, expected);
+        assert_eq!(statement.tile, tile(0, 8));
+    }
+
+    #[test]
+    fn parser_if() {
+        let mut tokens = spanned(vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(ScopeStart),
+            Id(0),
+            t(LineBreak),
+            t(Quote),
+            t(Quote),
+            t(LineBreak),
+            t(ScopeEnd),
+        ]);
+        let program = parse(&mut tokens).unwrap();
+
+        let node::Expression::Scope(scope) = &program.statements[1].expressions[0].value else {
+            panic!("expected a scope");
+        };
+        assert!(matches!(scope.kind, node::ScopeType::If));
+        assert!(matches!(
+            scope.signature.as_ref().unwrap().expressions[0].value,
+            node::Expression::Variable((0, _))
+        ));
+        assert_eq!(scope.body.statements.len(), 1);
+    }
+
+    #[test]
+    fn parser_operators() {
+        use node::{Expression::*, Operator::*};
+
+        let mut tokens = spanned(vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            // a in place
+            Id(0),
+            t(Increment),
+            t(Increment),
+            t(LineBreak),
+            // a - b stored in b
+            Id(0),
+            t(Decrement),
+            Id(1),
+            Id(1),
+            t(LineBreak),
+            // the store into a is kept
+            t(Zero),
+            Id(0),
+            t(Decrement),
+            t(LineBreak),
+        ]);
+        let program = parse(&mut tokens).unwrap();
+        let expression = |statement: usize, i: usize| &program.statements[statement].expressions[i];
+
+        let Assign((0, value)) = &expression(2, 0).value else {
+            panic!("expected an assignment");
+        };
+        let BinOp((left, Add, right)) = &value.value else {
+            panic!("expected an addition");
+        };
+        assert!(matches!(left.value, Variable((0, _))));
+        assert!(matches!(right.value, IntLit(2)));
+        assert_eq!(right.tile, tile(7, 2));
+
+        let BinOp((left, Subtract, right)) = &expression(3, 0).value else {
+            panic!("expected a subtraction");
+        };
+        assert!(matches!(left.value, Variable((0, _))));
+        assert!(matches!(right.value, Variable((1, _))));
+        assert_eq!(expression(3, 0).tile, tile(10, 3));
+        assert!(matches!(expression(3, 1).value, Variable((1, _))));
+
+        assert_eq!(program.statements[4].expressions.len(), 3);
+        let Assign((0, value)) = &expression(4, 2).value else {
+            panic!("expected an assignment");
+        };
+        assert!(
+            matches!(&value.value, BinOp((_, Add, right)) if matches!(right.value, IntLit(-1)))
+        );
+    }
+
+    #[test]
+    fn parser_input() {
+        use node::{Expression::*, Operator::*};
+
+        // the next byte minus one stored in a
+        let mut tokens = spanned(vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(Token::Input),
+            t(Decrement),
+            t(Zero),
+            t(Increment),
+            Id(0),
+            t(LineBreak),
+        ]);
+        let program = parse(&mut tokens).unwrap();
+        let statement = &program.statements[1];
+
+        let BinOp((left, Subtract, right)) = &statement.expressions[0].value else {
+            panic!("expected a subtraction");
+        };
+        assert!(matches!(left.value, Input));
+        assert!(matches!(right.value, IntLit(1)));
+        assert!(matches!(statement.expressions[1].value, Variable((0, _))));
+    }
+
+    #[test]
+    fn parser_call() {
+        // f returns its parameter, then x = f(1)
+        let mut tokens = spanned(vec![
+            t(ScopeStart),
+            t(Access),
+            Id(0),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            Id(1),
+            Id(0),
+            t(LineBreak),
+            t(ScopeEnd),
+            t(Access),
+            Id(2),
+            t(LineBreak),
+            Id(0),
+            t(Zero),
+            t(Increment),
+            Id(2),
+            t(LineBreak),
+        ]);
+        let program = parse(&mut tokens).unwrap();
+
+        let node::Expression::Scope(scope) = &program.statements[0].expressions[0].value else {
+            panic!("expected a scope");
+        };
+        assert_eq!((scope.name(), scope.parameters()), (Some(0), vec![1]));
+        // inside the body the name is a variable
+        assert!(matches!(
+            scope.body.statements[0].expressions[1].value,
+            node::Expression::Variable((0, _))
+        ));
+
+        let call = &program.statements[2].expressions[0];
+        let node::Expression::Call((0, arguments)) = &call.value else {
+            panic!("expected a call");
+        };
+        assert!(matches!(
+            arguments[..],
+            [Spanned {
+                value: node::Expression::IntLit(1),
+                ..
+            }]
+        ));
+        assert_eq!(call.tile, tile(13, 3));
+    }
+
+    #[test]
+    fn parser_quote() {
+        let mut tokens = spanned(vec![
+            t(Quote),
+            t(Increment),
+            t(Increment),
+            t(Zero),
+            t(Increment),
+            t(Quote),
+            t(LineBreak),
+            t(Quote),
+            t(Quote),
+            t(LineBreak),
+        ]);
+        let program = parse(&mut tokens).unwrap();
+
+        let test: Vec<&str> = program
+            .statements
+            .iter()
+            .map(|statement| match &statement.expressions[0].value {
+                node::Expression::StringLit(string) => string.as_str(),
+                _ => panic!("expected a string"),
+            })
+            .collect();
+
+        assert_eq!(test, vec!["\u{2}\u{1}", ""]);
+        assert_eq!(program.statements[0].expressions[0].tile, tile(0, 6));
+    }
+
+    #[test]
+    fn parser_errors() {
+        let test = [
+            vec![t(Zero), t(LineBreak), t(ScopeEnd)],
+            vec![t(ScopeStart), t(Repeat), t(Zero), t(LineBreak)],
+            vec![t(ScopeStart), t(Quote), t(LineBreak), t(ScopeEnd)],
+            vec![t(Access), t(LineBreak)],
+            vec![t(Increment), t(LineBreak)],
+            vec![t(Quote), t(Increment), t(Zero), t(Decrement), t(Quote)],
+            vec![
+                t(ScopeStart),
+                t(Access),
+                Id(0),
+                t(Zero),
+                t(LineBreak),
+                t(ScopeEnd),
+            ],
+        ]
+        .map(|tokens| parse(&mut spanned(tokens)).err());
+        let expected = [
+            Some(Error::UnmatchedScopeEnd { tile: tile(2, 1) }),
+            Some(Error::UnclosedScope { tile: tile(0, 1) }),
+            Some(Error::InvalidScope { tile: tile(0, 1) }),
+            Some(Error::ExpectedIdentifier { tile: tile(1, 1) }),
+            Some(Error::UnexpectedToken {
+                token: Increment,
+                tile: tile(0, 1),
+            }),
+            Some(Error::InvalidCharacter { tile: tile(2, 2) }),
+            Some(Error::InvalidParameter { tile: tile(3, 1) }),
+        ];
+
+        assert_eq!(test, expected);
+    }
+}
@@ -0,0 +1,603 @@
+use crate::*;
+
+// checks a parsed program, collecting every error instead of stopping at the first
+// - variables are resolved to their declaration, a declaration is visible until the end of the
+//   scope it is in and shadows declarations of the same identifier in outer scopes
+// - later declarations of an identifier get ids after every identifier in the source so each variable
+//   has its own, even when the earlier one is no longer in scope
+// - variables are typed by the values stored into them, ones never given a value are integers
+// - conditions and operands have to be integers and calls take a value for each parameter
+
+struct Symbol {
+    variable: usize, // id in the tree, differs from the identifier when it shadows another
+    kind: SymbolType,
+    tile: Tile, // where it was declared
+}
+
+struct Analyser {
+    scopes: Vec<HashMap<usize, Symbol>>, // symbols declared in each open scope, innermost last
+    ended: HashMap<usize, Tile>,         // declarations whose scope has ended, for errors
+    declared: HashSet<usize>,            // identifiers declared so far, in scope or not
+    variables: usize,                    // next id for a later declaration
+    types: HashMap<usize, SymbolType>,   // type of each variable by its id in the tree
+    parameters: HashMap<usize, Vec<usize>>, // parameters of every function analysed so far
+    errors: Vec<Error>,
+}
+
+// calls `f` with every expression in the program, including ones inside other expressions
+fn visit(program: &mut node::Program, f: &mut impl FnMut(&mut node::Expression)) {
+    fn visit_expression(
+        expression: &mut node::Expression,
+        f: &mut impl FnMut(&mut node::Expression),
+    ) {
+        use node::Expression::*;
+
+        match expression {
+            Scope(scope) => {
+                if let Some(signature) = &mut scope.signature {
+                    visit_statement(signature, f);
+                }
+                visit(&mut scope.body, f);
+            }
+            Call((_, arguments)) => {
+                for argument in arguments {
+                    visit_expression(&mut argument.value, f);
+                }
+            }
+            BinOp((left, _, right)) => {
+                visit_expression(&mut left.value, f);
+                visit_expression(&mut right.value, f);
+            }
+            Assign((_, value)) => visit_expression(&mut value.value, f),
+            _ => (),
+        }
+        f(expression);
+    }
+
+    fn visit_statement(statement: &mut node::Statement, f: &mut impl FnMut(&mut node::Expression)) {
+        for spanned in &mut statement.expressions {
+            visit_expression(&mut spanned.value, f);
+        }
+    }
+
+    for s in &mut program.statements {
+        visit_statement(s, f);
+    }
+}
+
+impl Analyser {
+    fn analyse_program(&mut self, program: &mut node::Program) {
+        for statement in &mut program.statements {
+            self.analyse_statement(statement);
+        }
+    }
+
+    // returns the type of the statements value if it has one
+    fn analyse_statement(&mut self, statement: &mut node::Statement) -> Option<SymbolType> {
+        use node::Expression::*;
+
+        let mut value = None;
+        for expression in &mut statement.expressions {
+            let tile = expression.tile;
+            match (&mut expression.value, value) {
+                (Scope(scope), _) => self.analyse_scope(scope),
+                (ScopeEnd, _) => (),
+                // variables after a value store it
+                (Declaration((id, _)), Some(kind)) => {
+                    let identifier = *id;
+                    *id = self.declare(identifier, tile);
+                    self.store(identifier, *id, tile, kind);
+                }
+                (Variable((id, _)), Some(kind)) => {
+                    let identifier = *id;
+                    if let Some(variable) = self.resolve(identifier, tile) {
+                        *id = variable;
+                        self.store(identifier, variable, tile, kind);
+                    }
+                }
+                _ => value = Some(self.analyse_value(expression)),
+            }
+        }
+
+        value
+    }
+
+    // the type of an expression that is a value
+    fn analyse_value(&mut self, expression: &mut Spanned<node::Expression>) -> SymbolType {
+        use node::Expression::*;
+
+        let tile = expression.tile;
+        match &mut expression.value {
+            IntLit(_) | Input => SymbolType::Int,
+            StringLit(_) => SymbolType::String,
+            Declaration((id, _)) => {
+                *id = self.declare(*id, tile);
+                self.load(*id)
+            }
+            Variable((id, _)) => match self.resolve(*id, tile) {
+                Some(variable) => {
+                    *id = variable;
+                    self.load(variable)
+                }
+                None => SymbolType::Int,
+            },
+            Call((function, arguments)) => {
+                let types: Vec<(SymbolType, Tile)> = (arguments.iter_mut())
+                    .map(|argument| (self.analyse_value(argument), argument.tile))
+                    .collect();
+                let Some(variable) = self.resolve(*function, tile) else {
+                    return SymbolType::Int;
+                };
+                *function = variable;
+
+                let parameters = self.parameters.get(&variable).cloned();
+                let parameters = parameters.unwrap_or_default();
+                // parse_call takes no more values than there are parameters
+                if types.len() < parameters.len() {
+                    self.errors.push(Error::ExpectedArgument { tile });
+                }
+                // arguments are stored into the parameters
+                for (parameter, (kind, tile)) in parameters.into_iter().zip(types) {
+                    self.store(parameter, parameter, tile, kind);
+                }
+
+                // the function returns whatever it stores into its name
+                self.load(variable)
+            }
+            BinOp((left, _, right)) => {
+                for operand in [left, right] {
+                    if self.analyse_value(operand) != SymbolType::Int {
+                        self.errors.push(Error::ExpectedInt { tile: operand.tile });
+                    }
+                }
+                SymbolType::Int
+            }
+            Assign((id, value)) => {
+                let kind = self.analyse_value(value);
+                let identifier = *id;
+                if let Some(variable) = self.resolve(identifier, tile) {
+                    *id = variable;
+                    self.store(identifier, variable, tile, kind);
+                }
+                kind
+            }
+            // the parser only puts values here
+            Scope(_) | ScopeEnd => SymbolType::Undefined,
+        }
+    }
+
+    fn analyse_scope(&mut self, scope: &mut node::Scope) {
+        // the signature is declared inside the scope so parameters are local to it
+        self.scopes.push(HashMap::new());
+        let name = scope.name();
+
+        match (&scope.kind, &mut scope.signature) {
+            (node::ScopeType::Function, Some(signature)) => {
+                for expression in &mut signature.expressions {
+                    let tile = expression.tile;
+                    match &mut expression.value {
+                        node::Expression::Declaration((id, _)) => *id = self.declare(*id, tile),
+                        node::Expression::Variable((id, _)) => {
+                            if let Some(variable) = self.resolve(*id, tile) {
+                                *id = variable;
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            (_, Some(signature)) => {
+                // a signature without a value is zero
+                let condition = self.analyse_statement(signature);
+                if condition.is_some_and(|kind| kind != SymbolType::Int) {
+                    self.errors.push(Error::ExpectedInt {
+                        tile: signature.tile,
+                    });
+                }
+            }
+            _ => (),
+        }
+        self.analyse_program(&mut scope.body);
+
+        // `unwrap`: pushed above
+        for (id, symbol) in self.scopes.pop().unwrap() {
+            self.ended.insert(id, symbol.tile);
+        }
+
+        // inside the body the name is the return value, after it the name calls the function
+        if let (node::ScopeType::Function, Some(name), Some(variable)) =
+            (&scope.kind, name, scope.name())
+        {
+            let parameters = scope.parameters();
+            let symbol = Symbol {
+                variable,
+                kind: SymbolType::Function(parameters.len()),
+                // `unwrap`: the function is named by its signature
+                tile: scope.signature.as_ref().unwrap().expressions[0].tile,
+            };
+            self.parameters.insert(variable, parameters);
+            self.insert(name, symbol);
+        }
+    }
+
+    fn insert(&mut self, id: usize, symbol: Symbol) {
+        self.ended.remove(&id);
+        // `unwrap`: the outermost scope is never popped
+        self.scopes.last_mut().unwrap().insert(id, symbol);
+    }
+
+    // adds a variable to the innermost scope, returning its id in the tree
+    // declaring it again in the same scope reuses it, anywhere else it is a new variable
+    fn declare(&mut self, id: usize, tile: Tile) -> usize {
+        let variable = match self.scopes.last().and_then(|scope| scope.get(&id)) {
+            Some(symbol) if !matches!(symbol.kind, SymbolType::Function(_)) => symbol.variable,
+            // the first declaration keeps the identifiers id
+            _ if self.declared.insert(id) => id,
+            _ => {
+                self.variables += 1;
+                self.variables - 1
+            }
+        };
+        self.insert(
+            id,
+            Symbol {
+                variable,
+                kind: SymbolType::Undefined,
+                tile,
+            },
+        );
+        variable
+    }
+
+    // the id in the tree of the innermost declaration of an identifier
+    fn resolve(&mut self, id: usize, tile: Tile) -> Option<usize> {
+        if let Some(symbol) = self.scopes.iter().rev().find_map(|scope| scope.get(&id)) {
+            return Some(symbol.variable);
+        }
+
+        self.errors.push(match self.ended.get(&id) {
+            Some(&declared) => Error::OutOfScope { id, tile, declared },
+            None => Error::UndeclaredVariable { id, tile },
+        });
+        None
+    }
+
+    fn load(&self, variable: usize) -> SymbolType {
+        match self.types.get(&variable) {
+            Some(&kind) => kind,
+            None => SymbolType::Int,
+        }
+    }
+
+    // a variable holds the type of the first value stored into it
+    fn store(&mut self, id: usize, variable: usize, tile: Tile, kind: SymbolType) {
+        match self.types.get(&variable) {
+            Some(&expected) if expected != kind => self.errors.push(Error::TypeMismatch {
+                id,
+                tile,
+                expected,
+                found: kind,
+            }),
+            Some(_) => (),
+            None => {
+                self.types.insert(variable, kind);
+            }
+        }
+    }
+}
+
+// resolves and types the variables of a parsed program in place, returning every error found
+pub fn analyse(program: &mut node::Program) -> Vec<Error> {
+    let mut largest = None;
+    visit(program, &mut |expression| {
+        if let node::Expression::Variable((id, _))
+        | node::Expression::Declaration((id, _))
+        | node::Expression::Assign((id, _))
+        | node::Expression::Call((id, _)) = expression
+        {
+            largest = largest.max(Some(*id));
+        }
+    });
+
+    let mut analyser = Analyser {
+        scopes: vec![HashMap::new()],
+        ended: HashMap::new(),
+        declared: HashSet::new(),
+        variables: largest.map_or(0, |id| id + 1),
+        types: HashMap::new(),
+        parameters: HashMap::new(),
+        errors: Vec::new(),
+    };
+    analyser.analyse_program(program);
+
+    let types = analyser.types;
+    visit(program, &mut |expression| {
+        if let node::Expression::Variable((id, kind)) | node::Expression::Declaration((id, kind)) =
+            expression
+        {
+            *kind = types.get(id).copied().unwrap_or(SymbolType::Int);
+        }
+    });
+
+    analyser.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{spanned, t, tile};
+    use node::build::{boxed, scope, statement, variable};
+    use node::Expression::*;
+    use Lexeme::Identifier as Id;
+    use Token::{Access, Increment, LineBreak, Repeat, ScopeEnd, ScopeStart, Zero};
+
+    #[test]
+    fn semantic_scoping() {
+        let mut program = parse(&mut spanned(vec![
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(ScopeStart),
+            Id(0),
+            t(LineBreak),
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(Access),
+            Id(1),
+            Id(0),
+            t(LineBreak),
+            t(ScopeEnd),
+            Id(0),
+            t(LineBreak),
+        ]))
+        .unwrap();
+        assert_eq!(analyse(&mut program), Vec::new());
+        let variable = |statement: &node::Statement, i: usize| match statement.expressions[i].value
+        {
+            Variable((id, kind)) | Declaration((id, kind)) => {
+                assert_eq!(kind, SymbolType::Int);
+                id
+            }
+            _ => panic!("expected a variable"),
+        };
+
+        let Scope(scope) = &program.statements[1].expressions[0].value else {
+            panic!("expected a scope");
+        };
+        // the condition is the outer variable, the declaration in the body shadows it
+        assert_eq!(variable(scope.signature.as_ref().unwrap(), 0), 0);
+        let shadow = variable(&scope.body.statements[0], 0);
+        assert!(shadow > 1);
+        assert_eq!(variable(&scope.body.statements[1], 1), shadow);
+        // after the scope the outer variable is visible again
+        assert_eq!(variable(&program.statements[2], 0), 0);
+    }
+
+    #[test]
+    fn semantic_redeclaration() {
+        // a local of a function, a global of the same name, and the same function in two loops
+        let mut program = parse(&mut spanned(vec![
+            t(ScopeStart),
+            t(Access),
+            Id(0),
+            t(LineBreak),
+            t(Zero),
+            t(Increment),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            t(ScopeEnd),
+            t(Zero),
+            t(Increment),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            t(ScopeStart),
+            Id(1),
+            t(LineBreak),
+            t(ScopeStart),
+            t(Access),
+            Id(2),
+            t(LineBreak),
+            t(ScopeEnd),
+            t(ScopeEnd),
+            t(ScopeStart),
+            Id(1),
+            t(LineBreak),
+            t(ScopeStart),
+            t(Access),
+            Id(2),
+            t(LineBreak),
+            t(ScopeEnd),
+            t(ScopeEnd),
+        ]))
+        .unwrap();
+        assert_eq!(analyse(&mut program), Vec::new());
+        fn body(statement: &node::Statement) -> &node::Program {
+            match &statement.expressions[0].value {
+                Scope(scope) => &scope.body,
+                _ => panic!("expected a scope"),
+            }
+        }
+        let declared = |statement: &node::Statement, i: usize| match statement.expressions[i].value
+        {
+            Declaration((id, _)) => id,
+            _ => panic!("expected a declaration"),
+        };
+        let function = |statement: &node::Statement| match &statement.expressions[0].value {
+            Scope(scope) => scope.name().unwrap(),
+            _ => panic!("expected a scope"),
+        };
+
+        // the global is its own variable even though the local is out of scope
+        let local = declared(&body(&program.statements[0]).statements[0], 1);
+        assert_ne!(declared(&program.statements[1], 1), local);
+        // so is the second function
+        let first = function(&body(&program.statements[2]).statements[0]);
+        let second = function(&body(&program.statements[3]).statements[0]);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn semantic_errors() {
+        // every error is reported, not only the first
+        let mut program = parse(&mut spanned(vec![
+            Id(1),
+            t(LineBreak),
+            t(ScopeStart),
+            t(Access),
+            Id(0),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            t(ScopeEnd),
+            Id(0),
+            t(LineBreak),
+            t(ScopeStart),
+            t(Repeat),
+            t(Access),
+            Id(2),
+            t(LineBreak),
+            t(ScopeEnd),
+            Id(2),
+            t(LineBreak),
+        ]))
+        .unwrap();
+        let expected = vec![
+            Error::UndeclaredVariable {
+                id: 1,
+                tile: tile(0, 1),
+            },
+            Error::ExpectedArgument { tile: tile(9, 1) },
+            Error::OutOfScope {
+                id: 2,
+                tile: tile(17, 1),
+                declared: tile(13, 2),
+            },
+        ];
+
+        assert_eq!(analyse(&mut program), expected);
+    }
+
+    #[test]
+    fn semantic_error_cases() {
+        let test = [
+            vec![t(Access), Id(0), t(LineBreak), Id(1)],
+            vec![
+                t(ScopeStart),
+                t(Access),
+                Id(0),
+                t(Access),
+                Id(1),
+                t(LineBreak),
+                t(ScopeEnd),
+                Id(0),
+                t(LineBreak),
+            ],
+            vec![
+                t(ScopeStart),
+                t(Repeat),
+                t(Access),
+                Id(0),
+                t(LineBreak),
+                t(ScopeEnd),
+                Id(0),
+            ],
+        ]
+        .map(|tokens| analyse(&mut parse(&mut spanned(tokens)).unwrap()));
+        let expected = [
+            vec![Error::UndeclaredVariable {
+                id: 1,
+                tile: tile(3, 1),
+            }],
+            vec![Error::ExpectedArgument { tile: tile(7, 1) }],
+            vec![Error::OutOfScope {
+                id: 0,
+                tile: tile(6, 1),
+                declared: tile(2, 2),
+            }],
+        ];
+
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn semantic_arguments() {
+        // f returns its parameter, values after its argument are the rest of the statement
+        let mut program = parse(&mut spanned(vec![
+            t(ScopeStart),
+            t(Access),
+            Id(0),
+            t(Access),
+            Id(1),
+            t(LineBreak),
+            Id(1),
+            Id(0),
+            t(LineBreak),
+            t(ScopeEnd),
+            t(Access),
+            Id(2),
+            t(LineBreak),
+            Id(0),
+            t(Zero),
+            t(Increment),
+            t(Zero),
+            t(Increment),
+            t(Increment),
+            Id(2),
+            t(LineBreak),
+        ]))
+        .unwrap();
+
+        assert_eq!(analyse(&mut program), Vec::new());
+        let [call, value, store] = &program.statements[2].expressions[..] else {
+            panic!("expected a call, a value and a store");
+        };
+        assert!(matches!(&call.value, Call((0, arguments)) if arguments.len() == 1));
+        assert!(matches!(value.value, IntLit(2)));
+        assert!(matches!(store.value, Variable((2, SymbolType::Int))));
+    }
+
+    #[test]
+    fn semantic_types() {
+        let mut program = node::Program {
+            statements: vec![
+                statement(vec![Declaration((0, SymbolType::Undefined))]),
+                statement(vec![Assign((0, boxed(StringLit("a".to_string()))))]),
+                statement(vec![IntLit(1), variable(0)]),
+                statement(vec![BinOp((
+                    boxed(variable(0)),
+                    node::Operator::Add,
+                    boxed(IntLit(1)),
+                ))]),
+                statement(vec![scope(
+                    node::ScopeType::Loop,
+                    vec![variable(0)],
+                    vec![],
+                )]),
+            ],
+        };
+        let test = analyse(&mut program);
+        let expected = vec![
+            Error::TypeMismatch {
+                id: 0,
+                tile: Default::default(),
+                expected: SymbolType::String,
+                found: SymbolType::Int,
+            },
+            Error::ExpectedInt {
+                tile: Default::default(),
+            },
+            Error::ExpectedInt {
+                tile: Default::default(),
+            },
+        ];
+
+        assert_eq!(test, expected);
+        assert!(matches!(
+            program.statements[0].expressions[0].value,
+            Declaration((0, SymbolType::String))
+        ));
+    }
+}
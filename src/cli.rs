@@ -0,0 +1,301 @@
+// command line parsing
+// cram <command> [options] <key> <source>
+
+use std::env;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: cram <command> [options] <key> <source>
+       cram key check [options] <key>
+
+commands:
+    build     compile the source into an executable
+    run       interpret the source
+    lex       print the tokens of the source
+    parse     print the syntax tree of the source
+    check     only report errors in the source
+    render    render a c source into a source image
+    key check report every problem with a key file without lexing anything
+
+options:
+    -o, --output <path>    where to write the output
+    --emit <stage>         stop building at asm, obj, ir or c instead of an executable
+    --overlay <path>       draw the tokens, lines, scopes and errors found over the source
+    --cache <dir>          where read key files are cached, defaults to $XDG_CACHE_HOME/cram
+    --no-cache             always read the key file
+    --tile-size <pixels>   size of the key files tiles, inferred from its grid by default
+    --tolerance <0-255>    how far apart colours can be and still be the same, for lossy images
+    -v, --verbose          print the progress of each stage, repeat to also dump them
+    -q, --quiet            only print errors
+    -h, --help             print this message";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Build,
+    Run,
+    Lex,
+    Parse,
+    Check,
+    Render,
+    KeyCheck,
+}
+
+// what `build` stops at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Asm,
+    Obj,
+    Ir,
+    C,
+    Exe,
+}
+
+impl Emit {
+    // guesses the stage from the output files extension
+    fn from_path(path: &str) -> Self {
+        match path.rsplit_once('.').map(|(_, ext)| ext) {
+            Some("s") | Some("asm") => Emit::Asm,
+            Some("o") => Emit::Obj,
+            Some("ir") => Emit::Ir,
+            Some("c") => Emit::C,
+            _ => Emit::Exe,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub key: String,
+    pub source: String,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub overlay: Option<String>,
+    pub options: lexer::Options,
+    pub verbosity: u8, // 0 quiet, 1 normal, 2 progress, 3 dumps
+}
+
+impl Cli {
+    pub fn verbose(&self) -> bool {
+        self.verbosity >= 2
+    }
+}
+
+// $XDG_CACHE_HOME/cram, ~/.cache/cram or the temp directory
+fn default_cache() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("cram")
+}
+
+// Ok(None) means help was asked for
+pub fn parse(args: &[String]) -> Result<Option<Cli>, String> {
+    // short flags can be clustered, `-vvq` is `-v -v -q`
+    let args: Vec<String> = args
+        .iter()
+        .flat_map(|arg| match arg.strip_prefix('-') {
+            Some(flags) if flags.len() > 1 && !flags.starts_with('-') => {
+                flags.chars().map(|flag| format!("-{flag}")).collect()
+            }
+            _ => vec![arg.clone()],
+        })
+        .collect();
+    let mut args = args.iter();
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut emit = None;
+    let mut overlay = None;
+    let mut cache = Some(None);
+    let mut tile_size = None;
+    let mut tolerance = 0;
+    let mut verbosity: u8 = 1;
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .cloned()
+                .ok_or(format!("expected a value after {flag}"))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(value(arg)?),
+            "--overlay" => overlay = Some(value(arg)?),
+            "--cache" => cache = Some(Some(PathBuf::from(value(arg)?))),
+            "--no-cache" => cache = None,
+            "--tile-size" => {
+                let value = value(arg)?;
+                tile_size = Some(match value.parse() {
+                    Ok(size) if size > 0 => size,
+                    _ => return Err(format!("invalid tile size {value}")),
+                })
+            }
+            "--tolerance" => {
+                let value = value(arg)?;
+                tolerance = value
+                    .parse()
+                    .map_err(|_| format!("invalid tolerance {value}"))?;
+            }
+            "--emit" => {
+                emit = Some(match value(arg)?.as_str() {
+                    "asm" => Emit::Asm,
+                    "obj" => Emit::Obj,
+                    "ir" => Emit::Ir,
+                    "c" => Emit::C,
+                    "exe" => Emit::Exe,
+                    stage => return Err(format!("unknown emit stage {stage}")),
+                })
+            }
+            "-q" | "--quiet" => verbosity = 0,
+            "-v" | "--verbose" => verbosity = verbosity.saturating_add(1),
+            flag if flag.starts_with('-') => return Err(format!("unknown option {flag}")),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        Some("build") => Command::Build,
+        Some("run") => Command::Run,
+        Some("lex") => Command::Lex,
+        Some("parse") => Command::Parse,
+        Some("check") => Command::Check,
+        Some("render") => Command::Render,
+        Some("key") => match positional.next().as_deref() {
+            Some("check") => Command::KeyCheck,
+            Some(command) => return Err(format!("unknown command key {command}")),
+            None => return Err("expected a key command".to_string()),
+        },
+        Some(command) => return Err(format!("unknown command {command}")),
+        None => return Err("expected a command".to_string()),
+    };
+    let (key, source) = if command == Command::KeyCheck {
+        // key commands have no source
+        let Some(key) = positional.next() else {
+            return Err("expected a key file".to_string());
+        };
+        (key, String::new())
+    } else {
+        let (Some(key), Some(source)) = (positional.next(), positional.next()) else {
+            return Err("expected a key and a source file".to_string());
+        };
+        (key, source)
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument {extra}"));
+    }
+
+    if emit.is_some() && command != Command::Build {
+        return Err("--emit only applies to build".to_string());
+    }
+    if command == Command::Render && output.is_none() {
+        return Err("render needs an output image path".to_string());
+    }
+
+    Ok(Some(Cli {
+        command,
+        emit: emit.unwrap_or_else(|| output.as_deref().map_or(Emit::Exe, Emit::from_path)),
+        key,
+        source,
+        output,
+        overlay,
+        options: lexer::Options {
+            cache: cache.map(|dir| dir.unwrap_or_else(default_cache)),
+            tile_size,
+            tolerance,
+        },
+        verbosity,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn cli_parse() {
+        let cli = parse(&args("build key.png src.png -o out.c -v"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cli,
+            Cli {
+                command: Command::Build,
+                key: "key.png".to_string(),
+                source: "src.png".to_string(),
+                output: Some("out.c".to_string()),
+                emit: Emit::C,
+                overlay: None,
+                options: lexer::Options {
+                    cache: Some(default_cache()),
+                    tile_size: None,
+                    tolerance: 0,
+                },
+                verbosity: 2,
+            }
+        );
+
+        let cli = parse(&args("build -q --emit ir k s -o prog"))
+            .unwrap()
+            .unwrap();
+        assert_eq!((cli.emit, cli.verbosity), (Emit::Ir, 0));
+
+        let cli = parse(&args("run k s --overlay o.png")).unwrap().unwrap();
+        assert_eq!(cli.overlay, Some("o.png".to_string()));
+
+        let cli = parse(&args("lex k s --cache dir")).unwrap().unwrap();
+        assert_eq!(cli.options.cache, Some(PathBuf::from("dir")));
+        let cli = parse(&args("lex k s --no-cache --tile-size 32"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(cli.options.cache, None);
+        assert_eq!(cli.options.tile_size, Some(32));
+        let cli = parse(&args("run k s --tolerance 12")).unwrap().unwrap();
+        assert_eq!(cli.options.tolerance, 12);
+
+        let cli = parse(&args("key check k --tile-size 32")).unwrap().unwrap();
+        assert_eq!(cli.command, Command::KeyCheck);
+        assert_eq!((cli.key.as_str(), cli.source.as_str()), ("k", ""));
+        assert_eq!(cli.options.tile_size, Some(32));
+
+        assert_eq!(parse(&args("check k s --help")), Ok(None));
+    }
+
+    #[test]
+    fn cli_short_flags() {
+        let verbosity = |line| parse(&args(line)).unwrap().unwrap().verbosity;
+        assert_eq!(verbosity("run k s -vv"), 3);
+        assert_eq!(verbosity("run k s -v -vv"), 4);
+        assert_eq!(verbosity("run k s -vvq"), 0);
+        assert_eq!(verbosity("run k s -qv"), 1);
+        assert_eq!(verbosity(&format!("run k s -{}", "v".repeat(300))), u8::MAX);
+        assert_eq!(parse(&args("run k s -vh")), Ok(None));
+
+        let cli = parse(&args("build k s -vo out.s")).unwrap().unwrap();
+        assert_eq!((cli.verbosity, cli.emit), (2, Emit::Asm));
+        assert!(parse(&args("run k s -vx")).is_err());
+    }
+
+    #[test]
+    fn cli_errors() {
+        assert!(parse(&args("")).is_err());
+        assert!(parse(&args("compile k s")).is_err());
+        assert!(parse(&args("run k")).is_err());
+        assert!(parse(&args("run k s extra")).is_err());
+        assert!(parse(&args("run k s -o")).is_err());
+        assert!(parse(&args("run k s --emit c")).is_err());
+        assert!(parse(&args("build k s --emit wasm")).is_err());
+        assert!(parse(&args("render k s.c")).is_err());
+        assert!(parse(&args("run k s --tile-size 0")).is_err());
+        assert!(parse(&args("run k s --tolerance 256")).is_err());
+        assert!(parse(&args("key k")).is_err());
+        assert!(parse(&args("key check")).is_err());
+        assert!(parse(&args("key check k s")).is_err());
+    }
+}
@@ -1,43 +1,354 @@
+mod cli;
+
 use std::env;
-use std::path::Path;
-use std::process::Command;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use cli::{Cli, Command, Emit};
+
+// how cram failed, decides the exit code
+#[derive(Debug)]
+enum Failure {
+    Source(String), // the key or source is wrong
+    Io(String),     // reading or writing files or running the assembler and linker
+}
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 4 {
-        println!("Input the key file, source file, and output file paths as respective arguments");
-        return;
+impl Failure {
+    fn code(&self) -> u8 {
+        match self {
+            Failure::Source(_) => 1,
+            Failure::Io(_) => 3, // 2 is left for usage errors
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Source(e) | Failure::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<lexer::Error> for Failure {
+    fn from(e: lexer::Error) -> Self {
+        match e {
+            lexer::Error::Image(_) | lexer::Error::Io(_) => Failure::Io(e.to_string()),
+            _ => Failure::Source(e.to_string()),
+        }
+    }
+}
+
+impl From<parser::Error> for Failure {
+    fn from(e: parser::Error) -> Self {
+        Failure::Source(e.to_string())
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e.to_string())
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let cli = match cli::parse(&args) {
+        Ok(Some(cli)) => cli,
+        Ok(None) => {
+            println!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {e}\n\n{}", cli::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(e.code())
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Failure> {
+    match cli.command {
+        Command::Render => return render(cli),
+        Command::KeyCheck => return key_check(cli),
+        _ => {}
     }
 
     // lexer
-    let tokens = lexer::deserialize(&args[1], &args[2]).unwrap();
-    println!("{:?} ({})", tokens, tokens.len());
+    let mut overlay = lexer::Overlay::default();
+    let mut tokens =
+        match lexer::deserialize_overlay(&cli.key, &cli.source, &cli.options, &mut overlay) {
+            Ok(t) => t,
+            Err(e) => {
+                save_overlay(cli, &overlay)?;
+                return Err(e.into());
+            }
+        };
+    progress(cli, "Finished tokenizing");
+    if cli.verbosity >= 3 || cli.command == Command::Lex {
+        for token in &tokens {
+            println!("{} {:?}", token.tile, token.value);
+        }
+    }
+    if cli.command == Command::Lex {
+        return save_overlay(cli, &overlay);
+    }
 
     // parser
-    let program = match parser::parse(&mut tokens.into()) {
-        Ok(p) => p,
+    let mut parsed = parser::parse(&mut tokens);
+    let errors = match &mut parsed {
+        Ok(program) => parser::analyse(program),
         Err(e) => {
-            println!("{e}");
-            return;
+            overlay.errors.push(e.tile());
+            Vec::new()
         }
     };
-    println!("Finished parsing:");
-    println!("{program:?}");
-
-    // codegen
-    let out_name = format!(
-        "out/{}",
-        Path::new(&args[3]).file_stem().unwrap().to_str().unwrap()
-    );
-    codegen::generate(&program, &format!("{}.s", out_name)).expect("failed to asm write to file");
-
-    Command::new("nasm") // assemble
-        .args(["-felf64", &format!("{}.s", out_name)])
-        .output()
-        .expect("nasm failed");
-    Command::new("ld") // link
-        .arg(format!("{}.o", out_name))
-        .args(["-o", &args[3]])
+    overlay
+        .errors
+        .extend(errors.iter().map(parser::Error::tile));
+    save_overlay(cli, &overlay)?;
+    let program = parsed?;
+    progress(cli, "Finished parsing");
+
+    // every error found by analysing the program is reported at once
+    for error in &errors {
+        eprintln!("{error}");
+    }
+    if !errors.is_empty() {
+        return Err(Failure::Source(format!(
+            "{} errors in {}",
+            errors.len(),
+            cli.source
+        )));
+    }
+    progress(cli, "Finished analysing");
+    if cli.verbosity >= 3 || cli.command == Command::Parse {
+        println!("{program:#?}");
+    }
+
+    match cli.command {
+        Command::Check => {
+            if cli.verbosity >= 1 {
+                println!("no errors in {}", cli.source);
+            }
+            Ok(())
+        }
+        Command::Run => {
+            interpreter::run(&ir::lower(&program))?;
+            Ok(())
+        }
+        Command::Build => build(cli, &program),
+        _ => Ok(()),
+    }
+}
+
+fn build(cli: &Cli, program: &parser::node::Program) -> Result<(), Failure> {
+    // defaults to the sources name in the working directory
+    let out = match &cli.output {
+        Some(out) => PathBuf::from(out),
+        None => {
+            let path = PathBuf::from(Path::new(&cli.source).file_stem().unwrap_or_default());
+            match cli.emit {
+                Emit::Asm => path.with_extension("s"),
+                Emit::Obj => path.with_extension("o"),
+                Emit::Ir => path.with_extension("ir"),
+                Emit::C => path.with_extension("c"),
+                Emit::Exe => path,
+            }
+        }
+    };
+
+    let module = ir::lower(program);
+    if cli.verbosity >= 3 {
+        println!("{module}");
+    }
+    progress(cli, "Finished lowering");
+
+    // dumping the ir instead of compiling
+    if cli.emit == Emit::Ir {
+        fs::write(&out, module.to_string())?;
+        return written(cli, &out);
+    }
+
+    // transpiling to c instead of compiling
+    if cli.emit == Emit::C {
+        codegen::c::generate(&module, &out.to_string_lossy())?;
+        return written(cli, &out);
+    }
+
+    if cli.emit == Emit::Asm {
+        codegen::generate(&module, &out.to_string_lossy())?;
+        return written(cli, &out);
+    }
+
+    assemble(&module, &out, cli.emit == Emit::Exe, ["nasm", "ld"])?;
+    written(cli, &out)
+}
+
+// a directory of intermediate files, removed once dropped so failed builds don't leave them behind
+// its made fresh for every build so files next to the output are never overwritten
+struct Intermediates(PathBuf);
+
+impl Intermediates {
+    fn new() -> io::Result<Self> {
+        static BUILDS: AtomicUsize = AtomicUsize::new(0);
+        let n = BUILDS.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("cram-build-{}-{n}", process::id()));
+        fs::create_dir_all(&dir)?;
+        Ok(Intermediates(dir))
+    }
+}
+
+impl Drop for Intermediates {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// assembles the module into an object at `out`, or an executable when linking
+fn assemble(
+    module: &ir::Module,
+    out: &Path,
+    link: bool,
+    [assembler, linker]: [&str; 2],
+) -> Result<(), Failure> {
+    let dir = Intermediates::new()?;
+    let asm = dir.0.join("out.s");
+    let obj = match link {
+        true => dir.0.join("out.o"),
+        false => out.to_path_buf(),
+    };
+
+    codegen::generate(module, &asm.to_string_lossy())?;
+    tool(
+        assembler,
+        &[
+            "-felf64",
+            &asm.to_string_lossy(),
+            "-o",
+            &obj.to_string_lossy(),
+        ],
+    )?;
+    if link {
+        tool(
+            linker,
+            &[&obj.to_string_lossy(), "-o", &out.to_string_lossy()],
+        )?;
+    }
+    Ok(())
+}
+
+// renders a c source into a source image
+fn render(cli: &Cli) -> Result<(), Failure> {
+    // `unwrap`: cli::parse makes sure render has an output
+    let out = cli.output.as_ref().unwrap();
+    let source = fs::read_to_string(&cli.source)?;
+    let tokens = cfront::translate(&source).map_err(Failure::Source)?;
+    lexer::serialize(&cli.key, &tokens, out, &cli.options)?;
+    written(cli, Path::new(out))
+}
+
+// reports every problem with the key file instead of only the first
+fn key_check(cli: &Cli) -> Result<(), Failure> {
+    let problems = lexer::check_key(&cli.key, &cli.options)?;
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    if !problems.is_empty() {
+        return Err(Failure::Source(format!(
+            "{} problems in {}",
+            problems.len(),
+            cli.key
+        )));
+    }
+    if cli.verbosity >= 1 {
+        println!("no problems in {}", cli.key);
+    }
+    Ok(())
+}
+
+// runs an external tool, failing with its stderr
+fn tool(name: &str, args: &[&str]) -> Result<(), Failure> {
+    let output = process::Command::new(name)
+        .args(args)
         .output()
-        .expect("ld failed");
+        .map_err(|e| Failure::Io(format!("failed to run {name}: {e}")))?;
+    if !output.status.success() {
+        return Err(Failure::Io(format!(
+            "{name} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+fn save_overlay(cli: &Cli, overlay: &lexer::Overlay) -> Result<(), Failure> {
+    let Some(out) = &cli.overlay else {
+        return Ok(());
+    };
+    overlay.save(&cli.source, out)?;
+    written(cli, Path::new(out))
+}
+
+fn written(cli: &Cli, path: &Path) -> Result<(), Failure> {
+    if cli.verbosity >= 1 {
+        println!("outputted {}", path.display());
+    }
+    Ok(())
+}
+
+fn progress(cli: &Cli, message: &str) {
+    if cli.verbose() {
+        println!("{message}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a directory of its own for every test, tests run in parallel
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cram-{name}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn build_intermediates() {
+        let dir = scratch("intermediates");
+        let (asm, obj, exe) = (dir.join("p.s"), dir.join("p.o"), dir.join("p"));
+        let module = ir::lower(&parser::node::Program {
+            statements: Vec::new(),
+        });
+
+        // files next to the output are never touched, whether the build fails or not
+        fs::write(&asm, "mine").unwrap();
+        fs::write(&obj, "mine").unwrap();
+        for tools in [["false", "true"], ["true", "false"], ["cram-no-tool", "ld"]] {
+            assert!(assemble(&module, &exe, true, tools).is_err());
+        }
+        assert!(assemble(&module, &exe, true, ["true", "true"]).is_ok());
+        assert!(assemble(&module, &dir.join("q.o"), false, ["true", "true"]).is_ok());
+        assert_eq!(fs::read_to_string(&asm).unwrap(), "mine");
+        assert_eq!(fs::read_to_string(&obj).unwrap(), "mine");
+
+        // and the intermediates are gone once the build is
+        let intermediates = Intermediates::new().unwrap();
+        let path = intermediates.0.clone();
+        fs::write(path.join("out.s"), "").unwrap();
+        drop(intermediates);
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
 }